@@ -9,17 +9,61 @@ use std::path::Path;
 const DEFAULT_LEVEL: fn() -> LevelFilter = || LevelFilter::Debug;
 const DEFAULT_BIND: fn() -> String = || "127.0.0.1:9100".to_owned();
 const DEFAULT_TRACK_FOLDER: fn() -> String = || "tracks".to_owned();
+const DEFAULT_FSYNC_INTERVAL: fn() -> u64 = || 5;
+const DEFAULT_MAX_DISTANCE: fn() -> f64 = || 5.0;
+const DEFAULT_AIRPORT_TYPES: fn() -> Vec<String> = || {
+  vec![
+    "large_airport".to_owned(),
+    "medium_airport".to_owned(),
+    "small_airport".to_owned(),
+  ]
+};
+const DEFAULT_GEODATA_URL: fn() -> Option<String> = || {
+  Some(
+    "https://raw.githubusercontent.com/viert/ourairports-json/main/output/airport_list.json"
+      .to_owned(),
+  )
+};
+
+/// Defines when the track data is flushed to disk
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Durability {
+  /// leave flushing to the OS
+  #[default]
+  None,
+  /// fsync every `fsync_interval` seconds
+  Periodic,
+  /// fsync every entry before it's acknowledged to the client
+  Ack,
+}
 
 #[derive(Debug, Deserialize)]
 pub struct TrackConfig {
   #[serde(default = "DEFAULT_TRACK_FOLDER")]
   pub folder: String,
+  #[serde(default)]
+  pub durability: Durability,
+  #[serde(default = "DEFAULT_FSYNC_INTERVAL")]
+  pub fsync_interval: u64,
+  /// link new track files into a hash chain making modifications detectable
+  /// against the digest logged when an upload ends, see `track::integrity`
+  #[serde(default)]
+  pub chained: bool,
+  /// convert finished flights into the compressed archival layout
+  /// this many seconds after the upload stream closes
+  #[serde(default)]
+  pub archive_after: Option<u64>,
 }
 
 impl Default for TrackConfig {
   fn default() -> Self {
     Self {
       folder: DEFAULT_TRACK_FOLDER(),
+      durability: Default::default(),
+      fsync_interval: DEFAULT_FSYNC_INTERVAL(),
+      chained: false,
+      archive_after: None,
     }
   }
 }
@@ -52,6 +96,45 @@ impl Default for ServiceConfig {
   }
 }
 
+/// Defines where the airport data comes from. Both the airport list JSON
+/// and the OurAirports airports.csv are accepted.
+#[derive(Debug, Deserialize)]
+pub struct GeoDataConfig {
+  /// local airport list, takes precedence over `url`
+  #[serde(default)]
+  pub path: Option<String>,
+  #[serde(default = "DEFAULT_GEODATA_URL")]
+  pub url: Option<String>,
+  /// the last airport list downloaded from `url` is kept here
+  /// and used when the download fails
+  #[serde(default)]
+  pub cache: Option<String>,
+  /// OurAirports runways.csv, runways are not matched if it's not set
+  #[serde(default)]
+  pub runways: Option<String>,
+  /// departure and arrival airports are looked up within this many
+  /// nautical miles
+  #[serde(default = "DEFAULT_MAX_DISTANCE")]
+  pub max_distance: f64,
+  /// airport types considered for departure and arrival, e.g. heliports
+  /// and closed fields are not by default, an empty list matches any type
+  #[serde(default = "DEFAULT_AIRPORT_TYPES")]
+  pub airport_types: Vec<String>,
+}
+
+impl Default for GeoDataConfig {
+  fn default() -> Self {
+    Self {
+      path: None,
+      url: DEFAULT_GEODATA_URL(),
+      cache: None,
+      runways: None,
+      max_distance: DEFAULT_MAX_DISTANCE(),
+      airport_types: DEFAULT_AIRPORT_TYPES(),
+    }
+  }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ApiConfig {
   pub base_uri: String,
@@ -65,9 +148,13 @@ pub struct Config {
   pub log: LogConfig,
   #[serde(default)]
   pub service: ServiceConfig,
+  #[serde(default)]
+  pub geodata: GeoDataConfig,
   pub api: ApiConfig,
 }
 
+// figment errors are large and returned once at startup
+#[allow(clippy::result_large_err)]
 pub fn read_in_config<P: AsRef<Path>>(filename: P) -> Result<Config, Error> {
   Figment::new().merge(Toml::file(filename)).extract()
 }
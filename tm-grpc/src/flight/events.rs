@@ -0,0 +1,136 @@
+use super::route::RouteEvent;
+use crate::track::entry::{Event, EventKind, TrackPoint};
+
+/// Detects flight events by comparing consecutive track points,
+/// takeoffs follow the events of the route tracker so bounces are ignored
+#[derive(Debug, Default)]
+pub struct EventDetector {
+  last: Option<TrackPoint>,
+}
+
+impl EventDetector {
+  /// Creates a detector continuing from the given point, e.g. the last
+  /// point of a resumed flight, so that no events are reported for the
+  /// state the aircraft was already in
+  pub fn new(last: Option<TrackPoint>) -> Self {
+    Self { last }
+  }
+
+  pub fn process(&mut self, tp: &TrackPoint, route: Option<RouteEvent>) -> Vec<Event> {
+    let mut events = vec![];
+    if let Some(last) = &self.last {
+      let mut push = |kind| events.push(Event::new(tp.ts, kind));
+
+      if let Some(RouteEvent::Takeoff { .. } | RouteEvent::TouchAndGo { .. }) = route {
+        push(EventKind::Takeoff);
+      }
+
+      // gear_pct is the gear extension, events are reported
+      // when the gear is fully retracted or extended
+      if last.gear_pct > 0 && tp.gear_pct == 0 {
+        push(EventKind::GearUp);
+      } else if last.gear_pct < 100 && tp.gear_pct >= 100 {
+        push(EventKind::GearDown);
+      }
+
+      if last.flaps != tp.flaps {
+        push(EventKind::Flaps(tp.flaps));
+      }
+
+      if !last.ap_master && tp.ap_master {
+        push(EventKind::ApEngaged);
+      } else if last.ap_master && !tp.ap_master {
+        push(EventKind::ApDisengaged);
+      }
+
+      if !last.on_rwy && tp.on_rwy {
+        push(EventKind::RunwayEnter);
+      } else if last.on_rwy && !tp.on_rwy {
+        push(EventKind::RunwayExit);
+      }
+    }
+    self.last = Some(tp.clone());
+    events
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::flight::route::RouteTracker;
+
+  fn kinds(
+    detector: &mut EventDetector,
+    route: &mut RouteTracker,
+    tp: &TrackPoint,
+  ) -> Vec<EventKind> {
+    let event = route.process(tp);
+    detector
+      .process(tp, event)
+      .into_iter()
+      .map(|e| e.kind)
+      .collect()
+  }
+
+  #[test]
+  fn test_takeoff() {
+    let mut tp = TrackPoint {
+      ts: 1,
+      on_gnd: true,
+      on_rwy: true,
+      gear_pct: 100,
+      flaps: 1,
+      ..Default::default()
+    };
+    let mut detector = EventDetector::default();
+    let mut route = RouteTracker::default();
+    assert!(kinds(&mut detector, &mut route, &tp).is_empty());
+
+    tp.ts = 2;
+    tp.on_gnd = false;
+    assert_eq!(
+      kinds(&mut detector, &mut route, &tp),
+      vec![EventKind::Takeoff]
+    );
+
+    tp.ts = 3;
+    tp.on_rwy = false;
+    tp.gear_pct = 0;
+    tp.flaps = 0;
+    tp.ap_master = true;
+    assert_eq!(
+      kinds(&mut detector, &mut route, &tp),
+      vec![
+        EventKind::GearUp,
+        EventKind::Flaps(0),
+        EventKind::ApEngaged,
+        EventKind::RunwayExit
+      ]
+    );
+
+    tp.ts = 4;
+    assert!(kinds(&mut detector, &mut route, &tp).is_empty());
+  }
+
+  #[test]
+  fn test_bounce() {
+    let mut detector = EventDetector::default();
+    let mut route = RouteTracker::default();
+    let mut tp = TrackPoint {
+      alt_agl: 100.0,
+      gs: 130.0,
+      ..Default::default()
+    };
+    for (ts, on_gnd, alt_agl) in [
+      (1, false, 100.0),
+      (2, true, 0.0),
+      (3, false, 10.0),
+      (4, true, 0.0),
+    ] {
+      tp.ts = ts;
+      tp.on_gnd = on_gnd;
+      tp.alt_agl = alt_agl;
+      assert!(kinds(&mut detector, &mut route, &tp).is_empty());
+    }
+  }
+}
@@ -0,0 +1,357 @@
+use crate::{
+  geodata::{distance_nm, FT_IN_NM},
+  service::tangomike,
+  track::{
+    entry::{RunwayRef, TouchDown, TrackFileEntry, TrackPoint},
+    error::TrackFileError,
+  },
+};
+use std::collections::VecDeque;
+
+/// Approach points older than this are dropped, e.g. while taxiing
+const MAX_APPROACH_MS: u64 = 15 * 60 * 1000;
+
+/// Touchdowns within this time after the first one are bounces
+const BOUNCE_WINDOW_MS: u64 = 10000;
+
+/// Bank at touchdown beyond this risks a wingtip or engine strike
+const MAX_BANK: f64 = 5.0;
+
+/// Pitch at touchdown beyond this risks a tail strike
+const MAX_PITCH: f64 = 10.0;
+
+/// Height above ground the float distance is measured from
+const FLARE_HEIGHT_FT: f64 = 50.0;
+
+/// Heights above ground the approach stability is checked at
+const STABILITY_GATES_FT: [f64; 2] = [1000.0, 500.0];
+
+/// Allowed deviation from the final approach speed, in knots
+const SPEED_BELOW_KT: f64 = 5.0;
+const SPEED_ABOVE_KT: f64 = 20.0;
+
+/// Aircraft state when passing a stability gate on the approach
+#[derive(Debug, Clone, PartialEq)]
+pub struct StabilityGate {
+  /// height above ground of the gate, ft
+  pub height: f64,
+  pub ias: f64,
+  /// the speed is within the limits of the final approach speed,
+  /// which is taken as the speed at 50 ft
+  pub on_speed: bool,
+  pub gear_down: bool,
+  /// the flaps are set to the landing setting
+  pub flaps_set: bool,
+}
+
+impl StabilityGate {
+  pub fn is_stable(&self) -> bool {
+    self.on_speed && self.gear_down && self.flaps_set
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LandingReport {
+  pub ts: u64,
+  pub lat: f64,
+  pub lng: f64,
+  pub runway: Option<RunwayRef>,
+  /// the touchdown was inferred by the server
+  pub inferred: bool,
+  /// vertical speed at the first touchdown, ft/min
+  pub vs: f64,
+  /// highest G-force among the touchdowns of the landing
+  pub g_force: Option<f64>,
+  /// touchdowns following the first one within a few seconds
+  pub bounces: u32,
+  pub bank: f64,
+  pub pitch: f64,
+  pub bank_exceeded: bool,
+  pub pitch_exceeded: bool,
+  /// distance from passing 50 ft above ground to the touchdown, ft
+  pub float_distance: Option<f64>,
+  pub threshold_distance: Option<f64>,
+  pub centreline_offset: Option<f64>,
+  pub crosswind: Option<f64>,
+  /// stability at 1000 and 500 ft above ground, missing if the approach
+  /// hasn't passed the height within the track
+  pub gates: Vec<StabilityGate>,
+}
+
+impl LandingReport {
+  pub fn is_stable(&self) -> bool {
+    self.gates.len() == STABILITY_GATES_FT.len() && self.gates.iter().all(|g| g.is_stable())
+  }
+}
+
+/// Builds the landing reports from the track entries.
+///
+/// The points of the current approach are kept from the moment the aircraft
+/// descends below the highest gate, so a report is complete as soon as its
+/// first touchdown is seen, bounces only update it.
+#[derive(Debug, Default)]
+pub struct LandingAnalyzer {
+  approach: VecDeque<TrackPoint>,
+  reports: Vec<LandingReport>,
+}
+
+impl LandingAnalyzer {
+  pub fn process(&mut self, entry: &TrackFileEntry) {
+    match entry {
+      TrackFileEntry::TrackPoint(tp) => {
+        if tp.alt_agl > STABILITY_GATES_FT[0] {
+          self.approach.clear();
+        }
+        while self
+          .approach
+          .front()
+          .is_some_and(|first| first.ts + MAX_APPROACH_MS < tp.ts)
+        {
+          self.approach.pop_front();
+        }
+        self.approach.push_back(tp.clone());
+      }
+      TrackFileEntry::TouchDown(td) => self.touchdown(td),
+      TrackFileEntry::Event(_) => {}
+    }
+  }
+
+  pub fn finish(self) -> Vec<LandingReport> {
+    self.reports
+  }
+
+  fn touchdown(&mut self, td: &TouchDown) {
+    if let Some(report) = self.reports.last_mut() {
+      if td.ts >= report.ts && td.ts - report.ts <= BOUNCE_WINDOW_MS {
+        report.bounces += 1;
+        report.g_force = match (report.g_force, td.g_force) {
+          (Some(a), Some(b)) => Some(a.max(b)),
+          (a, b) => a.or(b),
+        };
+        return;
+      }
+    }
+
+    // touchdowns reported by the client may follow a few points
+    // of the landing roll
+    let approach: Vec<&TrackPoint> = self.approach.iter().filter(|tp| tp.ts <= td.ts).collect();
+    let final_ias = crossing(&approach, FLARE_HEIGHT_FT).map(|(_, tp)| tp.ias);
+    let flaps = approach.last().map(|tp| tp.flaps);
+    let gates = STABILITY_GATES_FT
+      .iter()
+      .filter_map(|&height| {
+        let (_, tp) = crossing(&approach, height)?;
+        Some(StabilityGate {
+          height,
+          ias: tp.ias,
+          on_speed: final_ias
+            .is_some_and(|ias| tp.ias >= ias - SPEED_BELOW_KT && tp.ias <= ias + SPEED_ABOVE_KT),
+          gear_down: tp.gear_pct >= 100,
+          flaps_set: flaps == Some(tp.flaps),
+        })
+      })
+      .collect();
+    let float_distance = crossing(&approach, FLARE_HEIGHT_FT).map(|(above, below)| {
+      // interpolate the point of passing the flare height
+      let span = above.alt_agl - below.alt_agl;
+      let k = if span > 0.0 {
+        (above.alt_agl - FLARE_HEIGHT_FT) / span
+      } else {
+        0.0
+      };
+      let lat = above.lat + (below.lat - above.lat) * k;
+      let lng = above.lng + (below.lng - above.lng) * k;
+      distance_nm(lat, lng, td.lat, td.lng) * FT_IN_NM
+    });
+
+    self.reports.push(LandingReport {
+      ts: td.ts,
+      lat: td.lat,
+      lng: td.lng,
+      runway: td.runway.clone(),
+      inferred: td.inferred,
+      vs: td.vel_nrm,
+      g_force: td.g_force,
+      bounces: 0,
+      bank: td.bank,
+      pitch: td.pitch,
+      bank_exceeded: td.bank.abs() > MAX_BANK,
+      pitch_exceeded: td.pitch.abs() > MAX_PITCH,
+      float_distance,
+      threshold_distance: td.threshold_distance,
+      centreline_offset: td.centreline_offset,
+      crosswind: td.crosswind,
+      gates,
+    });
+  }
+}
+
+/// Returns the last pair of points descending through the height
+fn crossing<'a>(
+  points: &[&'a TrackPoint],
+  height: f64,
+) -> Option<(&'a TrackPoint, &'a TrackPoint)> {
+  points
+    .windows(2)
+    .rev()
+    .find(|pair| pair[0].alt_agl >= height && pair[1].alt_agl < height)
+    .map(|pair| (pair[0], pair[1]))
+}
+
+impl From<StabilityGate> for tangomike::StabilityGate {
+  fn from(value: StabilityGate) -> Self {
+    Self {
+      height: value.height,
+      ias: value.ias,
+      on_speed: value.on_speed,
+      gear_down: value.gear_down,
+      flaps_set: value.flaps_set,
+      stable: value.is_stable(),
+    }
+  }
+}
+
+impl From<LandingReport> for tangomike::LandingReport {
+  fn from(value: LandingReport) -> Self {
+    let stable = value.is_stable();
+    let runway = value.runway.unwrap_or_default();
+    Self {
+      ts: value.ts,
+      lat: value.lat,
+      lng: value.lng,
+      airport: runway.airport,
+      runway: runway.ident,
+      inferred: value.inferred,
+      vs: value.vs,
+      g_force: value.g_force,
+      bounces: value.bounces,
+      bank: value.bank,
+      pitch: value.pitch,
+      bank_exceeded: value.bank_exceeded,
+      pitch_exceeded: value.pitch_exceeded,
+      float_distance: value.float_distance,
+      threshold_distance: value.threshold_distance,
+      centreline_offset: value.centreline_offset,
+      crosswind: value.crosswind,
+      gates: value.gates.into_iter().map(|g| g.into()).collect(),
+      stable,
+    }
+  }
+}
+
+/// Builds the landing reports of a flight
+pub fn analyze_landings<I>(entries: I) -> Result<Vec<LandingReport>, TrackFileError>
+where
+  I: Iterator<Item = Result<TrackFileEntry, TrackFileError>>,
+{
+  let mut analyzer = LandingAnalyzer::default();
+  for entry in entries {
+    analyzer.process(&entry?);
+  }
+  Ok(analyzer.finish())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn touchdown(ts: u64, g_force: f64) -> TrackFileEntry {
+    TrackFileEntry::TouchDown(TouchDown {
+      ts,
+      lat: 51.4650,
+      lng: -0.4200 - ts as f64 / 1000000.0,
+      vel_nrm: -180.0,
+      bank: 1.0,
+      pitch: 11.0,
+      g_force: Some(g_force),
+      ..Default::default()
+    })
+  }
+
+  #[test]
+  fn test_landing() -> Result<(), TrackFileError> {
+    let entries = vec![
+      TrackFileEntry::TrackPoint(TrackPoint::at(0).pos(51.4650, -0.42).agl(3000.0).ias(250.0)),
+      TrackFileEntry::TrackPoint(
+        TrackPoint::at(100000)
+          .pos(51.4650, -0.52)
+          .agl(1100.0)
+          .ias(170.0)
+          .config(100, 2),
+      ),
+      TrackFileEntry::TrackPoint(
+        TrackPoint::at(110000)
+          .pos(51.4650, -0.53)
+          .agl(900.0)
+          .ias(150.0)
+          .config(100, 2),
+      ),
+      TrackFileEntry::TrackPoint(
+        TrackPoint::at(130000)
+          .pos(51.4650, -0.55)
+          .agl(450.0)
+          .ias(140.0)
+          .config(100, 3),
+      ),
+      TrackFileEntry::TrackPoint(
+        TrackPoint::at(150000)
+          .pos(51.4650, -0.57)
+          .agl(60.0)
+          .ias(138.0)
+          .config(100, 3),
+      ),
+      TrackFileEntry::TrackPoint(
+        TrackPoint::at(152000)
+          .pos(51.4650, -0.572)
+          .agl(40.0)
+          .ias(137.0)
+          .config(100, 3),
+      ),
+      TrackFileEntry::TrackPoint(
+        TrackPoint::at(155000)
+          .pos(51.4650, -0.575)
+          .agl(0.0)
+          .ias(135.0)
+          .config(100, 3)
+          .on_gnd(true),
+      ),
+      touchdown(155000, 1.4),
+      TrackFileEntry::TrackPoint(
+        TrackPoint::at(156000)
+          .pos(51.4650, -0.576)
+          .agl(2.0)
+          .ias(134.0)
+          .config(100, 3),
+      ),
+      touchdown(157000, 1.6),
+      TrackFileEntry::TrackPoint(
+        TrackPoint::at(158000)
+          .pos(51.4650, -0.578)
+          .agl(0.0)
+          .ias(130.0)
+          .config(100, 3)
+          .on_gnd(true),
+      ),
+    ];
+    let reports = analyze_landings(entries.into_iter().map(Ok))?;
+    assert_eq!(reports.len(), 1);
+    let report = &reports[0];
+    assert_eq!(report.vs, -180.0);
+    assert_eq!(report.bounces, 1);
+    assert_eq!(report.g_force, Some(1.6));
+    assert!(!report.bank_exceeded);
+    assert!(report.pitch_exceeded);
+
+    // 50 ft is passed at 151000, the track moves 0.004 deg west to 155000
+    let float = report.float_distance.unwrap();
+    assert!((float - 0.004 * 60.0 * FT_IN_NM * 51.465f64.to_radians().cos()).abs() < 10.0);
+
+    // flaps are not set to the landing setting at 1000 ft yet
+    assert_eq!(report.gates.len(), 2);
+    assert!(report.gates[0].on_speed);
+    assert!(!report.gates[0].flaps_set);
+    assert!(report.gates[1].is_stable());
+    assert!(!report.is_stable());
+    Ok(())
+  }
+}
@@ -0,0 +1,7 @@
+pub mod events;
+pub mod landing;
+pub mod phase;
+pub mod route;
+pub mod runway;
+pub mod summary;
+pub mod touchdown;
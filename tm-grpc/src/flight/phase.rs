@@ -0,0 +1,282 @@
+use super::route::RouteEvent;
+use crate::track::{
+  entry::{Event, EventKind, FlightPhase, TrackFileEntry, TrackPoint},
+  error::TrackFileError,
+};
+
+/// Ground speed in knots above which the aircraft is taxiing
+const MOVING_GS: f64 = 3.0;
+
+/// Height above ground the takeoff phase ends at
+const TAKEOFF_AGL: f64 = 1000.0;
+
+/// Height above ground below which the aircraft configured for landing
+/// is on the approach
+const APPROACH_AGL: f64 = 3000.0;
+
+/// Climb or descent rate in ft/min below which the aircraft is level
+const LEVEL_VS: f64 = 300.0;
+
+/// Time the vertical trend has to hold to switch between climb,
+/// cruise and descent, so that short level-offs and bumps are ignored
+const TREND_MS: u64 = 30000;
+
+/// Time the aircraft has to stay still after landing to be parked
+const PARKED_MS: u64 = 60000;
+
+/// Number of entries searched back for the last phase event,
+/// about an hour of track at the usual rate
+const PHASE_SCAN_LEN: usize = 4000;
+
+/// Follows the flight through its phases from preflight to parked.
+///
+/// Takeoffs and landings follow the route tracker, so a bounce stays
+/// in the landing phase. Taxiing and parking follow the ground speed,
+/// the takeoff ends at 1000 ft above ground and the approach starts below
+/// 3000 ft with the gear or flaps extended. Climb, cruise and descent
+/// follow the vertical trend.
+#[derive(Debug, Default)]
+pub struct PhaseTracker {
+  phase: Option<FlightPhase>,
+  last: Option<TrackPoint>,
+  /// phase the vertical trend points to and the time it started
+  trend: Option<(FlightPhase, u64)>,
+  stopped_since: Option<u64>,
+}
+
+impl PhaseTracker {
+  /// Creates a tracker continuing from the given phase and point,
+  /// e.g. the last ones of a resumed flight
+  pub fn new(phase: Option<FlightPhase>, last: Option<TrackPoint>) -> Self {
+    Self {
+      phase,
+      last,
+      ..Default::default()
+    }
+  }
+
+  pub fn phase(&self) -> Option<FlightPhase> {
+    self.phase
+  }
+
+  /// Returns the new phase if the point or its route event changes it
+  pub fn process(&mut self, tp: &TrackPoint, route: Option<RouteEvent>) -> Option<FlightPhase> {
+    use FlightPhase::*;
+    if tp.gs < MOVING_GS {
+      self.stopped_since.get_or_insert(tp.ts);
+    } else {
+      self.stopped_since = None;
+    }
+
+    let phase = match route {
+      Some(RouteEvent::TakeoffRoll)
+      | Some(RouteEvent::Takeoff { .. })
+      | Some(RouteEvent::TouchAndGo { .. }) => Takeoff,
+      Some(RouteEvent::RejectedTakeoff) => TaxiOut,
+      Some(RouteEvent::TouchDown) => Landing,
+      Some(RouteEvent::FullStop { .. }) => TaxiIn,
+      None if tp.on_gnd => self.ground_phase(tp),
+      None => self.air_phase(tp),
+    };
+    self.last = Some(tp.clone());
+
+    if self.phase == Some(phase) {
+      None
+    } else {
+      self.phase = Some(phase);
+      self.trend = None;
+      Some(phase)
+    }
+  }
+
+  fn ground_phase(&self, tp: &TrackPoint) -> FlightPhase {
+    use FlightPhase::*;
+    match self.phase {
+      None | Some(Preflight) if tp.gs >= MOVING_GS => TaxiOut,
+      None => Preflight,
+      Some(TaxiIn) if self.is_parked(tp) => Parked,
+      Some(Parked) if tp.gs >= MOVING_GS => TaxiOut,
+      Some(phase) => phase,
+    }
+  }
+
+  fn air_phase(&mut self, tp: &TrackPoint) -> FlightPhase {
+    use FlightPhase::*;
+    let vs = self.vertical_speed(tp);
+    let configured = tp.gear_pct >= 100 || tp.flaps > 0;
+    let on_approach = tp.alt_agl < APPROACH_AGL && vs < LEVEL_VS && configured;
+    let trend = if vs >= LEVEL_VS {
+      Climb
+    } else if vs <= -LEVEL_VS {
+      Descent
+    } else {
+      Cruise
+    };
+
+    match self.phase {
+      // the flight has started in the air
+      None if on_approach => Approach,
+      None => trend,
+      Some(Takeoff) if tp.alt_agl >= TAKEOFF_AGL => Climb,
+      // the ground phases end in the air by a route event only,
+      // so a bounce stays in the landing
+      Some(phase @ (Takeoff | Landing | Preflight | TaxiOut | TaxiIn | Parked)) => phase,
+      Some(Climb) | Some(Cruise) | Some(Descent) if on_approach => Approach,
+      // only a go-around ends the approach in the air
+      Some(Approach) if trend != Climb => Approach,
+      Some(phase) => self.follow_trend(phase, trend, tp.ts),
+    }
+  }
+
+  fn follow_trend(&mut self, phase: FlightPhase, trend: FlightPhase, ts: u64) -> FlightPhase {
+    if phase == trend {
+      self.trend = None;
+      return phase;
+    }
+    match self.trend {
+      Some((pending, since)) if pending == trend => {
+        if ts >= since + TREND_MS {
+          trend
+        } else {
+          phase
+        }
+      }
+      _ => {
+        self.trend = Some((trend, ts));
+        phase
+      }
+    }
+  }
+
+  fn is_parked(&self, tp: &TrackPoint) -> bool {
+    let Some(since) = self.stopped_since else {
+      return false;
+    };
+    tp.eng_running == Some(false) || tp.parking_brake == Some(true) || tp.ts >= since + PARKED_MS
+  }
+
+  fn vertical_speed(&self, tp: &TrackPoint) -> f64 {
+    if let Some(vs) = tp.vs {
+      return vs;
+    }
+    match &self.last {
+      Some(last) if tp.ts > last.ts => {
+        (tp.alt_amsl - last.alt_amsl) / ((tp.ts - last.ts) as f64 / 60000.0)
+      }
+      _ => 0.0,
+    }
+  }
+}
+
+/// Finds the phase of the flight before the entry at `idx` by looking
+/// back for the last phase event. The search is limited to the last
+/// `PHASE_SCAN_LEN` entries, the phase is unknown if there's none within
+/// them. The phase at the end of a track is kept in its header.
+pub fn phase_before<F>(idx: usize, read_at: F) -> Result<Option<FlightPhase>, TrackFileError>
+where
+  F: Fn(usize) -> Result<TrackFileEntry, TrackFileError>,
+{
+  for pos in (idx.saturating_sub(PHASE_SCAN_LEN)..idx).rev() {
+    if let TrackFileEntry::Event(Event {
+      kind: EventKind::Phase(phase),
+      ..
+    }) = read_at(pos)?
+    {
+      return Ok(Some(phase));
+    }
+  }
+  Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::flight::route::RouteTracker;
+
+  fn phases(points: &[TrackPoint]) -> Vec<FlightPhase> {
+    let mut route = RouteTracker::default();
+    let mut tracker = PhaseTracker::default();
+    points
+      .iter()
+      .filter_map(|tp| tracker.process(tp, route.process(tp)))
+      .collect()
+  }
+
+  #[test]
+  fn test_phases() {
+    use FlightPhase::*;
+    let points = [
+      TrackPoint::at(0).on_gnd(true),
+      TrackPoint::at(10000).on_gnd(true).gs(10.0),
+      TrackPoint::at(20000).on_gnd(true).gs(80.0),
+      TrackPoint::at(30000)
+        .gs(150.0)
+        .agl(500.0)
+        .vs(2000.0)
+        .config(100, 0),
+      TrackPoint::at(40000)
+        .gs(180.0)
+        .agl(1500.0)
+        .vs(2000.0)
+        .config(100, 0),
+      TrackPoint::at(100000).gs(300.0).agl(10000.0).vs(100.0),
+      // a short level-off isn't cruise yet
+      TrackPoint::at(110000).gs(300.0).agl(10000.0).vs(0.0),
+      TrackPoint::at(150000).gs(300.0).agl(10000.0).vs(0.0),
+      TrackPoint::at(200000).gs(300.0).agl(9000.0).vs(-1500.0),
+      TrackPoint::at(240000).gs(300.0).agl(8000.0).vs(-1500.0),
+      TrackPoint::at(300000)
+        .gs(150.0)
+        .agl(1500.0)
+        .vs(-700.0)
+        .config(100, 0),
+      TrackPoint::at(320000).on_gnd(true).gs(130.0),
+      TrackPoint::at(330000).on_gnd(true).gs(20.0),
+      TrackPoint::at(340000).on_gnd(true),
+      TrackPoint::at(410000).on_gnd(true),
+    ];
+    assert_eq!(
+      phases(&points),
+      vec![Preflight, TaxiOut, Takeoff, Climb, Cruise, Descent, Approach, Landing, TaxiIn, Parked]
+    );
+  }
+
+  #[test]
+  fn test_phase_before() -> Result<(), TrackFileError> {
+    let event = |pos: usize| {
+      let kind = match pos {
+        0 => EventKind::Phase(FlightPhase::Preflight),
+        _ => EventKind::GearUp,
+      };
+      Ok(TrackFileEntry::Event(Event::new(pos as u64, kind)))
+    };
+    assert_eq!(phase_before(0, event)?, None);
+    assert_eq!(phase_before(100, event)?, Some(FlightPhase::Preflight));
+    // too far back
+    assert_eq!(phase_before(PHASE_SCAN_LEN + 1, event)?, None);
+    Ok(())
+  }
+
+  #[test]
+  fn test_bounce() {
+    use FlightPhase::*;
+    let points = [
+      TrackPoint::at(0)
+        .gs(150.0)
+        .agl(1500.0)
+        .vs(-700.0)
+        .config(100, 0),
+      TrackPoint::at(10000).on_gnd(true).gs(130.0),
+      TrackPoint::at(11000)
+        .gs(125.0)
+        .agl(15.0)
+        .vs(200.0)
+        .config(100, 0),
+      TrackPoint::at(13000).on_gnd(true).gs(120.0),
+      TrackPoint::at(20000).on_gnd(true).gs(20.0),
+      TrackPoint::at(30000).on_gnd(true),
+      TrackPoint::at(100000).on_gnd(true),
+    ];
+    assert_eq!(phases(&points), vec![Approach, Landing, TaxiIn, Parked]);
+  }
+}
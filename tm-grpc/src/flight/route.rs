@@ -0,0 +1,277 @@
+use crate::track::entry::TrackPoint;
+
+/// Ground speed in knots above which the aircraft on the ground
+/// is considered to be on the takeoff roll
+const TAKEOFF_ROLL_GS: f64 = 40.0;
+
+/// Ground speed in knots below which the landing roll or a rejected
+/// takeoff is considered to be over
+const TAXI_GS: f64 = 30.0;
+
+/// Landings left within this time are bounces rather than touch-and-goes
+const BOUNCE_MS: u64 = 10000;
+
+/// Height above ground in feet a bounce doesn't reach, the aircraft
+/// climbing above it after a landing is making a touch-and-go
+const BOUNCE_AGL: f64 = 50.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RouteEvent {
+  /// The aircraft has accelerated to the takeoff roll speed
+  TakeoffRoll,
+  /// The aircraft has slowed down to the taxi speed on the takeoff roll
+  RejectedTakeoff,
+  /// The aircraft has lifted off, the position is where the takeoff roll started
+  Takeoff { lat: f64, lng: f64 },
+  /// The aircraft has touched down after a flight, bounces don't repeat it
+  TouchDown,
+  /// The aircraft has landed and taken off again without stopping
+  TouchAndGo { lat: f64, lng: f64 },
+  /// The aircraft has landed and slowed down to the taxi speed
+  FullStop { lat: f64, lng: f64 },
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+enum State {
+  #[default]
+  Unknown,
+  Ground,
+  TakeoffRoll {
+    lat: f64,
+    lng: f64,
+  },
+  Airborne,
+  LandingRoll {
+    lat: f64,
+    lng: f64,
+  },
+  Bounced {
+    lat: f64,
+    lng: f64,
+    since: u64,
+  },
+}
+
+/// Follows the flight from the takeoff roll to the final full stop
+/// telling takeoffs, touch-and-goes, bounces and full stop landings apart.
+/// The flight events, phases and inferred touchdowns follow its events
+/// rather than the raw on_gnd transitions.
+///
+/// The landing position is where the aircraft first touched down, so
+/// bounces and a long landing roll don't move it to a neighbouring airport.
+/// A flight that starts in the air never reports a takeoff.
+#[derive(Debug, Default)]
+pub struct RouteTracker {
+  state: State,
+}
+
+impl RouteTracker {
+  /// Creates a tracker continuing from the given point, e.g. the last
+  /// point of a resumed flight
+  pub fn new(last: Option<&TrackPoint>) -> Self {
+    let state = match last {
+      Some(tp) if tp.on_gnd => State::Ground,
+      Some(_) => State::Airborne,
+      None => State::Unknown,
+    };
+    Self { state }
+  }
+
+  pub fn process(&mut self, tp: &TrackPoint) -> Option<RouteEvent> {
+    let (state, event) = match self.state {
+      State::Unknown if !tp.on_gnd => (State::Airborne, None),
+      // the takeoff roll has been missed between two points
+      State::Ground if !tp.on_gnd => (
+        State::Airborne,
+        Some(RouteEvent::Takeoff {
+          lat: tp.lat,
+          lng: tp.lng,
+        }),
+      ),
+      State::Unknown | State::Ground if tp.gs >= TAKEOFF_ROLL_GS => (
+        State::TakeoffRoll {
+          lat: tp.lat,
+          lng: tp.lng,
+        },
+        Some(RouteEvent::TakeoffRoll),
+      ),
+      State::Unknown => (State::Ground, None),
+      State::TakeoffRoll { lat, lng } if !tp.on_gnd => {
+        (State::Airborne, Some(RouteEvent::Takeoff { lat, lng }))
+      }
+      State::TakeoffRoll { .. } if tp.gs < TAXI_GS => {
+        (State::Ground, Some(RouteEvent::RejectedTakeoff))
+      }
+      State::Airborne if tp.on_gnd => (
+        State::LandingRoll {
+          lat: tp.lat,
+          lng: tp.lng,
+        },
+        Some(RouteEvent::TouchDown),
+      ),
+      State::LandingRoll { lat, lng } if !tp.on_gnd => (
+        State::Bounced {
+          lat,
+          lng,
+          since: tp.ts,
+        },
+        None,
+      ),
+      State::LandingRoll { lat, lng } if tp.gs < TAXI_GS => {
+        (State::Ground, Some(RouteEvent::FullStop { lat, lng }))
+      }
+      State::Bounced { lat, lng, .. } if tp.on_gnd => (State::LandingRoll { lat, lng }, None),
+      State::Bounced { lat, lng, since }
+        if tp.ts >= since + BOUNCE_MS || tp.alt_agl >= BOUNCE_AGL =>
+      {
+        (State::Airborne, Some(RouteEvent::TouchAndGo { lat, lng }))
+      }
+      state => (state, None),
+    };
+    self.state = state;
+    event
+  }
+
+  /// Completes the flight when the stream ends, the aircraft still rolling
+  /// out or bouncing after a landing is considered to have made a full stop
+  pub fn finish(&mut self) -> Option<RouteEvent> {
+    match self.state {
+      State::LandingRoll { lat, lng } | State::Bounced { lat, lng, .. } => {
+        self.state = State::Ground;
+        Some(RouteEvent::FullStop { lat, lng })
+      }
+      _ => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn run(tracker: &mut RouteTracker, points: &[(u64, f64, bool, f64)]) -> Vec<RouteEvent> {
+    points
+      .iter()
+      .filter_map(|&(ts, lat, on_gnd, gs)| {
+        let tp = TrackPoint {
+          ts,
+          lat,
+          on_gnd,
+          gs,
+          ..Default::default()
+        };
+        tracker.process(&tp)
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_touch_and_go() {
+    let mut tracker = RouteTracker::default();
+    let events = run(
+      &mut tracker,
+      &[
+        (0, 1.0, true, 0.0),
+        (1000, 1.0, true, 50.0),
+        (2000, 1.1, false, 140.0),
+        (3000, 2.0, true, 120.0),
+        (4000, 2.1, false, 120.0),
+        (5000, 2.1, true, 110.0),
+        (6000, 2.2, false, 130.0),
+        (20000, 3.0, false, 130.0),
+        (21000, 4.0, true, 130.0),
+        (22000, 4.1, true, 20.0),
+      ],
+    );
+    assert_eq!(
+      events,
+      vec![
+        RouteEvent::TakeoffRoll,
+        RouteEvent::Takeoff { lat: 1.0, lng: 0.0 },
+        RouteEvent::TouchDown,
+        RouteEvent::TouchAndGo { lat: 2.0, lng: 0.0 },
+        RouteEvent::TouchDown,
+        RouteEvent::FullStop { lat: 4.0, lng: 0.0 },
+      ]
+    );
+    assert!(tracker.finish().is_none());
+  }
+
+  #[test]
+  fn test_climb_after_landing() {
+    let mut tracker = RouteTracker::new(Some(&TrackPoint::default()));
+    let mut tp = TrackPoint {
+      lat: 1.0,
+      on_gnd: true,
+      gs: 120.0,
+      ..Default::default()
+    };
+    assert_eq!(tracker.process(&tp), Some(RouteEvent::TouchDown));
+    tp.on_gnd = false;
+    tp.alt_agl = 20.0;
+    assert_eq!(tracker.process(&tp), None);
+    // climbing higher than a bounce ends the landing right away
+    tp.ts = 2000;
+    tp.alt_agl = 100.0;
+    assert_eq!(
+      tracker.process(&tp),
+      Some(RouteEvent::TouchAndGo { lat: 1.0, lng: 0.0 })
+    );
+  }
+
+  #[test]
+  fn test_bounce_at_end() {
+    let mut tracker = RouteTracker::default();
+    let events = run(
+      &mut tracker,
+      &[
+        (0, 1.0, false, 140.0),
+        (1000, 2.0, true, 120.0),
+        (2000, 2.1, false, 110.0),
+      ],
+    );
+    assert_eq!(events, vec![RouteEvent::TouchDown]);
+    assert_eq!(
+      tracker.finish(),
+      Some(RouteEvent::FullStop { lat: 2.0, lng: 0.0 })
+    );
+  }
+
+  #[test]
+  fn test_started_in_air() {
+    let mut tracker = RouteTracker::default();
+    let events = run(
+      &mut tracker,
+      &[(0, 1.0, false, 200.0), (1000, 2.0, true, 120.0)],
+    );
+    assert_eq!(events, vec![RouteEvent::TouchDown]);
+    assert_eq!(
+      tracker.finish(),
+      Some(RouteEvent::FullStop { lat: 2.0, lng: 0.0 })
+    );
+  }
+
+  #[test]
+  fn test_rejected_takeoff() {
+    let mut tracker = RouteTracker::default();
+    let events = run(
+      &mut tracker,
+      &[
+        (0, 1.0, true, 0.0),
+        (1000, 1.0, true, 60.0),
+        (2000, 1.0, true, 10.0),
+        (3000, 2.0, true, 60.0),
+        (4000, 2.1, false, 140.0),
+      ],
+    );
+    assert_eq!(
+      events,
+      vec![
+        RouteEvent::TakeoffRoll,
+        RouteEvent::RejectedTakeoff,
+        RouteEvent::TakeoffRoll,
+        RouteEvent::Takeoff { lat: 2.0, lng: 0.0 }
+      ]
+    );
+  }
+}
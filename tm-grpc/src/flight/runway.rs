@@ -0,0 +1,69 @@
+use crate::{
+  geodata::{runway::RunwayMatch, GeoData},
+  track::entry::{Event, EventKind, RunwayRef, TouchDown, TrackPoint},
+};
+
+impl From<&RunwayMatch<'_>> for RunwayRef {
+  fn from(value: &RunwayMatch<'_>) -> Self {
+    Self {
+      airport: value.runway.airport_ident.clone(),
+      ident: value.end.ident.clone(),
+    }
+  }
+}
+
+/// Matches the touchdown to the runway and fills in the position on it
+pub fn locate_touchdown(geo: &GeoData, td: &mut TouchDown) {
+  let Some(m) = geo.match_runway(td.lat, td.lng, td.hdg_true) else {
+    return;
+  };
+  td.runway = Some((&m).into());
+  td.threshold_distance = Some(m.threshold_distance);
+  td.centreline_offset = Some(m.centreline_offset);
+  td.crosswind = match (td.wind_dir, td.wind_vel) {
+    (Some(wind_dir), Some(wind_vel)) => Some(m.crosswind(wind_dir, wind_vel)),
+    _ => None,
+  };
+}
+
+/// Matches a takeoff event to the runway at the liftoff point
+pub fn locate_takeoff(geo: &GeoData, event: &mut Event, tp: &TrackPoint) {
+  if event.kind != EventKind::Takeoff {
+    return;
+  }
+  event.runway = geo
+    .match_runway(tp.lat, tp.lng, tp.hdg_true)
+    .map(|m| (&m).into());
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_locate_touchdown() {
+    let geo = GeoData::fixture();
+    let mut td = TouchDown {
+      lat: 51.46493,
+      lng: -0.44522,
+      hdg_true: 272.0,
+      wind_dir: Some(240.0),
+      wind_vel: Some(10.0),
+      ..Default::default()
+    };
+    locate_touchdown(&geo, &mut td);
+    assert_eq!(td.runway.as_ref().unwrap().to_string(), "EGLL 27L");
+    // wind from the left
+    assert!(td.crosswind.unwrap() < -4.0);
+
+    let mut ev = Event::new(0, EventKind::Takeoff);
+    let tp = TrackPoint {
+      lat: 51.1480,
+      lng: -0.1900,
+      hdg_true: 257.0,
+      ..Default::default()
+    };
+    locate_takeoff(&geo, &mut ev, &tp);
+    assert_eq!(ev.runway.unwrap().to_string(), "EGKK 26L");
+  }
+}
@@ -0,0 +1,204 @@
+use super::{
+  landing::LandingAnalyzer,
+  route::{RouteEvent, RouteTracker},
+};
+use crate::{
+  service::tangomike,
+  track::{
+    entry::{TrackFileEntry, TrackPoint},
+    error::TrackFileError,
+  },
+};
+use serde::{Deserialize, Serialize};
+
+/// Ground speed in knots above which the aircraft is moving on its own
+const MOVING_GS: f64 = 3.0;
+
+/// Intervals between points longer than this are data gaps
+const GAP_MS: u64 = 30000;
+
+/// Climb or descent rate in ft/min below which the aircraft is level
+const LEVEL_VS: f64 = 300.0;
+
+/// Level flight below this height above ground is not cruise
+const MIN_CRUISE_AGL: f64 = 3000.0;
+
+/// Flight statistics, timestamps are in milliseconds like the entries
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FlightSummary {
+  /// the aircraft started moving on the ground before the first takeoff
+  pub block_off: Option<u64>,
+  pub takeoff: Option<u64>,
+  /// the last touchdown, bounces excluded
+  pub landing: Option<u64>,
+  /// the aircraft stopped after the last landing
+  pub block_on: Option<u64>,
+  /// time between liftoffs and touchdowns, ms
+  pub air_time: u64,
+  /// nm
+  pub distance: f64,
+  /// ft
+  pub max_alt: f64,
+  /// kt
+  pub max_gs: f64,
+  /// time-weighted altitude of level flight well above ground, ft
+  pub avg_cruise_alt: Option<f64>,
+  /// landings excluding bounces
+  pub landings: u32,
+  /// intervals between consecutive points longer than 30 seconds
+  pub gaps: u32,
+}
+
+impl FlightSummary {
+  /// Computes the summary from all the entries of a flight
+  pub fn compute<I>(entries: I) -> Result<Self, TrackFileError>
+  where
+    I: Iterator<Item = Result<TrackFileEntry, TrackFileError>>,
+  {
+    let mut summary = Self::default();
+    let mut landings = LandingAnalyzer::default();
+    let mut route = RouteTracker::default();
+    let mut last: Option<TrackPoint> = None;
+    let mut last_moving = None;
+    let mut stopped = None;
+    let mut cruise_time = 0;
+    let mut cruise_alt = 0.0;
+
+    for entry in entries {
+      let entry = entry?;
+      landings.process(&entry);
+      let TrackFileEntry::TrackPoint(tp) = entry else {
+        continue;
+      };
+
+      summary.distance = tp.distance;
+      summary.max_alt = summary.max_alt.max(tp.alt_amsl);
+      summary.max_gs = summary.max_gs.max(tp.gs);
+
+      if tp.gs >= MOVING_GS {
+        if tp.on_gnd && summary.takeoff.is_none() && summary.block_off.is_none() {
+          summary.block_off = Some(tp.ts);
+        }
+        last_moving = Some(tp.ts);
+        stopped = None;
+      } else if stopped.is_none() {
+        stopped = Some(tp.ts);
+      }
+
+      match route.process(&tp) {
+        Some(RouteEvent::Takeoff { .. }) if summary.takeoff.is_none() => {
+          summary.takeoff = Some(tp.ts)
+        }
+        Some(RouteEvent::TouchDown) => summary.landing = Some(tp.ts),
+        _ => {}
+      }
+
+      if let Some(last) = &last {
+        let dt = tp.ts.saturating_sub(last.ts);
+        if dt > GAP_MS {
+          summary.gaps += 1;
+        }
+
+        if !last.on_gnd {
+          summary.air_time += dt;
+        }
+        if !last.on_gnd && !tp.on_gnd {
+          let vs = match tp.vs {
+            Some(vs) => vs,
+            None if dt > 0 => (tp.alt_amsl - last.alt_amsl) / (dt as f64 / 60000.0),
+            None => 0.0,
+          };
+          if vs.abs() < LEVEL_VS && tp.alt_agl >= MIN_CRUISE_AGL && dt <= GAP_MS {
+            cruise_time += dt;
+            cruise_alt += tp.alt_amsl * dt as f64;
+          }
+        }
+      }
+      last = Some(tp);
+    }
+
+    if let Some(landing) = summary.landing {
+      // the flight may end while still taxiing
+      summary.block_on = match (stopped, last_moving) {
+        (Some(stopped), _) if stopped > landing => Some(stopped),
+        (_, Some(moving)) if moving > landing => Some(moving),
+        _ => None,
+      };
+    }
+    if cruise_time > 0 {
+      summary.avg_cruise_alt = Some(cruise_alt / cruise_time as f64);
+    }
+    summary.landings = landings.finish().len() as u32;
+    Ok(summary)
+  }
+}
+
+impl From<FlightSummary> for tangomike::FlightSummary {
+  fn from(value: FlightSummary) -> Self {
+    Self {
+      block_off: value.block_off,
+      takeoff: value.takeoff,
+      landing: value.landing,
+      block_on: value.block_on,
+      air_time: value.air_time,
+      distance: value.distance,
+      max_alt: value.max_alt,
+      max_gs: value.max_gs,
+      avg_cruise_alt: value.avg_cruise_alt,
+      landings: value.landings,
+      gaps: value.gaps,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::track::entry::TouchDown;
+
+  #[test]
+  fn test_summary() -> Result<(), TrackFileError> {
+    let entries = vec![
+      TrackFileEntry::TrackPoint(TrackPoint::at(0).on_gnd(true)),
+      TrackFileEntry::TrackPoint(TrackPoint::at(10000).on_gnd(true).gs(10.0)),
+      TrackFileEntry::TrackPoint(TrackPoint::at(20000).on_gnd(true).gs(140.0)),
+      TrackFileEntry::TrackPoint(TrackPoint::at(30000).agl(400.0).gs(160.0)),
+      TrackFileEntry::TrackPoint(TrackPoint::at(60000).agl(9900.0).gs(300.0)),
+      TrackFileEntry::TrackPoint(TrackPoint::at(70000).agl(9900.0).gs(450.0)),
+      TrackFileEntry::TrackPoint(TrackPoint::at(80000).agl(9900.0).gs(450.0)),
+      // data gap in cruise
+      TrackFileEntry::TrackPoint(TrackPoint::at(180000).agl(9900.0).gs(450.0)),
+      TrackFileEntry::TrackPoint(TrackPoint::at(190000).agl(9900.0).gs(450.0)),
+      TrackFileEntry::TrackPoint(TrackPoint::at(210000).agl(2900.0).gs(250.0)),
+      TrackFileEntry::TrackPoint(TrackPoint::at(230000).on_gnd(true).gs(130.0)),
+      TrackFileEntry::TouchDown(TouchDown {
+        ts: 230000,
+        ..Default::default()
+      }),
+      TrackFileEntry::TrackPoint(TrackPoint::at(240000).on_gnd(true).gs(15.0)),
+      TrackFileEntry::TrackPoint(TrackPoint::at(250000).on_gnd(true)),
+      TrackFileEntry::TrackPoint(TrackPoint::at(260000).on_gnd(true)),
+    ];
+    // the distance flown is accumulated as the points are appended
+    let entries = entries.into_iter().map(|entry| match entry {
+      TrackFileEntry::TrackPoint(tp) => Ok(TrackFileEntry::TrackPoint(TrackPoint {
+        distance: tp.ts as f64 / 10000.0,
+        ..tp
+      })),
+      entry => Ok(entry),
+    });
+    let summary = FlightSummary::compute(entries)?;
+    assert_eq!(summary.block_off, Some(10000));
+    assert_eq!(summary.takeoff, Some(30000));
+    assert_eq!(summary.landing, Some(230000));
+    assert_eq!(summary.block_on, Some(250000));
+    assert_eq!(summary.air_time, 200000);
+    assert_eq!(summary.distance, 26.0);
+    assert_eq!(summary.max_alt, 10000.0);
+    assert_eq!(summary.max_gs, 450.0);
+    assert_eq!(summary.avg_cruise_alt, Some(10000.0));
+    assert_eq!(summary.landings, 1);
+    assert_eq!(summary.gaps, 1);
+    Ok(())
+  }
+}
@@ -0,0 +1,197 @@
+use super::route::RouteEvent;
+use crate::{
+  geodata::angle_diff,
+  track::entry::{TouchDown, TrackPoint},
+};
+
+/// Time to wait for a client touchdown before an inferred one is reported
+const CONFIRM_DELAY_MS: u64 = 5000;
+
+/// Client and inferred touchdowns closer than this are the same landing
+const MATCH_WINDOW_MS: u64 = 10000;
+
+/// Transitions to on_gnd from higher above ground are treated as glitches
+/// or slewing rather than a landing
+const MAX_AGL_FT: f64 = 100.0;
+
+/// Infers touchdowns from the landings detected by the route tracker
+/// for clients which don't report them, bounces are not touchdowns.
+///
+/// An inferred touchdown is held back for a few seconds and dropped if
+/// the client reports the same landing in the meantime, so it's reported
+/// after a few newer track points. The upload holds those points back
+/// while a touchdown is pending to write them in time order.
+#[derive(Debug, Default)]
+pub struct TouchDownInference {
+  last: Option<TrackPoint>,
+  prev: Option<TrackPoint>,
+  pending: Option<TouchDown>,
+  client_ts: Option<u64>,
+}
+
+impl TouchDownInference {
+  /// Creates an inference continuing from the given point,
+  /// e.g. the last point of a resumed flight
+  pub fn new(last: Option<TrackPoint>) -> Self {
+    Self {
+      last,
+      ..Default::default()
+    }
+  }
+
+  /// Tells if an inferred touchdown waits for confirmation
+  pub fn is_pending(&self) -> bool {
+    self.pending.is_some()
+  }
+
+  /// Registers a touchdown reported by the client
+  pub fn client_touchdown(&mut self, td: &TouchDown) {
+    self.client_ts = Some(td.ts);
+    if self
+      .pending
+      .as_ref()
+      .is_some_and(|pending| pending.ts.abs_diff(td.ts) <= MATCH_WINDOW_MS)
+    {
+      self.pending = None;
+    }
+  }
+
+  /// Processes the next track point along with its route event,
+  /// returns an inferred touchdown once it's confirmed
+  pub fn process(&mut self, tp: &TrackPoint, route: Option<RouteEvent>) -> Option<TouchDown> {
+    let confirmed = match &self.pending {
+      Some(pending) if tp.ts >= pending.ts + CONFIRM_DELAY_MS => self.pending.take(),
+      _ => None,
+    };
+
+    if let Some(last) = &self.last {
+      let landed = route == Some(RouteEvent::TouchDown) && last.alt_agl <= MAX_AGL_FT;
+      let reported = self
+        .client_ts
+        .is_some_and(|ts| ts.abs_diff(tp.ts) <= MATCH_WINDOW_MS);
+      if landed && !reported {
+        self.pending = Some(self.infer(last, tp));
+      }
+    }
+
+    self.prev = self.last.replace(tp.clone());
+    confirmed
+  }
+
+  /// Returns the pending touchdown when the stream ends
+  pub fn finish(&mut self) -> Option<TouchDown> {
+    self.pending.take()
+  }
+
+  fn infer(&self, last: &TrackPoint, tp: &TrackPoint) -> TouchDown {
+    TouchDown {
+      ts: tp.ts,
+      bank: tp.bank.unwrap_or_default(),
+      hdg_mag: tp.hdg_mag.unwrap_or(tp.hdg_true),
+      hdg_true: tp.hdg_true,
+      vel_nrm: self.estimate_vs(last, tp),
+      pitch: tp.pitch.unwrap_or_default(),
+      lat: tp.lat,
+      lng: tp.lng,
+      g_force: tp.g_force,
+      gs: Some(tp.gs),
+      ias: Some(tp.ias),
+      wind_vel: Some(tp.wind_vel),
+      wind_dir: Some(tp.wind_dir),
+      crab_angle: Some(angle_diff(tp.hdg_true, tp.crs)),
+      on_rwy: Some(tp.on_rwy),
+      inferred: true,
+      ..Default::default()
+    }
+  }
+
+  /// Estimates the vertical speed at touchdown in ft/min, from the reported
+  /// vertical speed if the client sends it or from the altitude change
+  /// between the last airborne points otherwise
+  fn estimate_vs(&self, last: &TrackPoint, tp: &TrackPoint) -> f64 {
+    if let Some(vs) = last.vs {
+      return vs;
+    }
+    let (from, to) = match &self.prev {
+      Some(prev) if !prev.on_gnd => (prev, last),
+      _ => (last, tp),
+    };
+    let dt = to.ts.saturating_sub(from.ts);
+    if dt == 0 {
+      return 0.0;
+    }
+    (to.alt_amsl - from.alt_amsl) / (dt as f64 / 60000.0)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::flight::route::RouteTracker;
+
+  /// Feeds the points through a route tracker and the inference
+  #[derive(Default)]
+  struct Landing {
+    route: RouteTracker,
+    inference: TouchDownInference,
+  }
+
+  impl Landing {
+    fn process(&mut self, tp: &TrackPoint) -> Option<TouchDown> {
+      let event = self.route.process(tp);
+      self.inference.process(tp, event)
+    }
+  }
+
+  #[test]
+  fn test_inferred() {
+    let mut inference = Landing::default();
+    assert!(inference.process(&TrackPoint::at(0).agl(20.0)).is_none());
+    assert!(inference.process(&TrackPoint::at(1000).agl(10.0)).is_none());
+    assert!(inference
+      .process(&TrackPoint::at(2000).on_gnd(true))
+      .is_none());
+    assert!(inference
+      .process(&TrackPoint::at(3000).on_gnd(true))
+      .is_none());
+    assert!(inference.inference.is_pending());
+
+    let td = inference
+      .process(&TrackPoint::at(7000).on_gnd(true))
+      .unwrap();
+    assert!(!inference.inference.is_pending());
+    assert!(td.inferred);
+    assert_eq!(td.ts, 2000);
+    assert_eq!(td.vel_nrm, -600.0);
+    assert!(inference.inference.finish().is_none());
+  }
+
+  #[test]
+  fn test_bounce() {
+    let mut inference = Landing::default();
+    inference.process(&TrackPoint::at(0).agl(10.0));
+    inference.process(&TrackPoint::at(1000).on_gnd(true));
+    inference.process(&TrackPoint::at(2000).agl(5.0));
+    inference.process(&TrackPoint::at(3000).on_gnd(true));
+    let td = inference
+      .process(&TrackPoint::at(7000).on_gnd(true))
+      .unwrap();
+    assert_eq!(td.ts, 1000);
+    assert!(inference.inference.finish().is_none());
+  }
+
+  #[test]
+  fn test_reported_by_client() {
+    let mut inference = Landing::default();
+    inference.process(&TrackPoint::at(0).agl(10.0));
+    inference.process(&TrackPoint::at(1000).on_gnd(true));
+    inference.inference.client_touchdown(&TouchDown {
+      ts: 1200,
+      ..Default::default()
+    });
+    assert!(inference
+      .process(&TrackPoint::at(7000).on_gnd(true))
+      .is_none());
+    assert!(inference.inference.finish().is_none());
+  }
+}
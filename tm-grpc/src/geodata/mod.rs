@@ -1,13 +1,16 @@
+pub mod runway;
+
 use chrono::Utc;
-use log::info;
-use rstar::{PointDistance, RTree, RTreeObject, AABB};
+use log::{info, warn};
+use rstar::{primitives::GeomWithData, RTree};
 use serde::Deserialize;
-use std::error::Error;
+use std::{collections::HashMap, error::Error, f64::consts::FRAC_PI_2, path::Path, time::Duration};
+use tempfile::Builder;
 
-use crate::util::seconds_since;
+use self::runway::{parse_runways, Runway, RunwayMatch};
+use crate::{config::GeoDataConfig, util::seconds_since};
 
-const OURAIRPORTS_URL: &str =
-  "https://raw.githubusercontent.com/viert/ourairports-json/main/output/airport_list.json";
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
 
 #[derive(Debug, Deserialize)]
 pub struct Airport {
@@ -29,61 +32,387 @@ pub struct Airport {
   pub keywords: Vec<String>,
 }
 
-impl RTreeObject for Airport {
-  type Envelope = AABB<(f64, f64)>;
+/// Airport as listed in the OurAirports airports.csv
+#[derive(Debug, Deserialize)]
+struct CsvAirport {
+  id: u32,
+  ident: String,
+  #[serde(rename = "type")]
+  airport_type: String,
+  name: String,
+  latitude_deg: f64,
+  longitude_deg: f64,
+  elevation_ft: Option<i32>,
+  gps_code: String,
+  iata_code: String,
+  local_code: String,
+  home_link: String,
+  wikipedia_link: String,
+  keywords: String,
+}
 
-  fn envelope(&self) -> Self::Envelope {
-    AABB::from_point((self.lng, self.lat))
+impl From<CsvAirport> for Airport {
+  fn from(value: CsvAirport) -> Self {
+    Self {
+      id: value.id,
+      ident: value.ident,
+      airport_type: value.airport_type,
+      name: value.name,
+      lat: value.latitude_deg,
+      lng: value.longitude_deg,
+      elevation_ft: value.elevation_ft,
+      gps_code: value.gps_code,
+      iata_code: value.iata_code,
+      local_code: value.local_code,
+      home_link: value.home_link,
+      wikipedia_link: value.wikipedia_link,
+      keywords: value
+        .keywords
+        .split(',')
+        .map(|kw| kw.trim().to_owned())
+        .filter(|kw| !kw.is_empty())
+        .collect(),
+    }
   }
 }
 
-impl PointDistance for Airport {
-  fn distance_2(
-    &self,
-    point: &<Self::Envelope as rstar::Envelope>::Point,
-  ) -> <<Self::Envelope as rstar::Envelope>::Point as rstar::Point>::Scalar {
-    let (lng, lat) = *point;
-    let lat_diff = (lat - self.lat).abs();
-    let mut lng_diff = lng - self.lng;
-    if lng_diff < -180.0 {
-      lng_diff += 360.0;
-    } else if lng_diff < 0.0 {
-      lng_diff = lng_diff.abs();
-    }
-    lat_diff * lat_diff + lng_diff * lng_diff
+/// Mean Earth radius in nautical miles
+const EARTH_RADIUS_NM: f64 = 3440.065;
+
+pub const FT_IN_NM: f64 = 6076.12;
+
+/// Position on the unit sphere. Chord lengths between such points grow
+/// monotonically with the great-circle distance, so the nearest neighbours
+/// in this space are the nearest ones on the globe, with no special cases
+/// at the antimeridian or the poles.
+fn unit_vector(lat: f64, lng: f64) -> [f64; 3] {
+  let (lat, lng) = (lat.to_radians(), lng.to_radians());
+  [lat.cos() * lng.cos(), lat.cos() * lng.sin(), lat.sin()]
+}
+
+/// Great-circle distance in nautical miles
+pub fn distance_nm(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> f64 {
+  let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+  let dlat = lat2 - lat1;
+  let dlng = (lng2 - lng1).to_radians();
+  let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlng / 2.0).sin().powi(2);
+  2.0 * a.sqrt().min(1.0).asin() * EARTH_RADIUS_NM
+}
+
+/// Signed difference between two headings in degrees, within -180..180
+pub fn angle_diff(a: f64, b: f64) -> f64 {
+  let diff = (a - b).rem_euclid(360.0);
+  if diff > 180.0 {
+    diff - 360.0
+  } else {
+    diff
   }
 }
 
 #[derive(Debug)]
 pub struct GeoData {
-  airports: RTree<Airport>,
+  airports: RTree<GeomWithData<[f64; 3], Airport>>,
+  /// runways by airport ident
+  runways: HashMap<String, Vec<Runway>>,
+  max_distance: f64,
+  airport_types: Vec<String>,
 }
 
 impl GeoData {
   pub fn new(airports: Vec<Airport>) -> Self {
     info!("indexing geodata...");
     let t1 = Utc::now();
-    let mut tree = RTree::new();
-    for airport in airports {
-      tree.insert(airport);
-    }
+    let airports = airports
+      .into_iter()
+      .map(|arpt| GeomWithData::new(unit_vector(arpt.lat, arpt.lng), arpt))
+      .collect();
+    let tree = RTree::bulk_load(airports);
     info!("geodata indexed in {}s", seconds_since(t1));
-    Self { airports: tree }
+    let cfg = GeoDataConfig::default();
+    Self {
+      airports: tree,
+      runways: HashMap::new(),
+      max_distance: cfg.max_distance,
+      airport_types: cfg.airport_types,
+    }
+  }
+
+  /// Sets the limits used by `closest_airport`
+  pub fn set_lookup(&mut self, max_distance: f64, airport_types: &[String]) {
+    self.max_distance = max_distance;
+    self.airport_types = airport_types.to_vec();
+  }
+
+  /// Loads the airports from the local file if it's configured or
+  /// downloads them otherwise
+  pub async fn load(cfg: &GeoDataConfig) -> Result<Self, Box<dyn Error>> {
+    let mut geo = match (&cfg.path, &cfg.url) {
+      (Some(path), _) => Self::open(path)?,
+      (None, Some(url)) => Self::download(url, cfg.cache.as_deref()).await?,
+      (None, None) => return Err("neither geodata.path nor geodata.url is set".into()),
+    };
+    geo.set_lookup(cfg.max_distance, &cfg.airport_types);
+    if let Some(path) = &cfg.runways {
+      // runways only add details, the service can do without them
+      if let Err(err) = geo.open_runways(path) {
+        warn!("error loading runways from {path}: {err}");
+      }
+    }
+    Ok(geo)
   }
 
-  pub async fn load() -> Result<Self, Box<dyn Error>> {
-    info!("loading geodata...");
+  /// Loads the airports from a local airport list JSON or OurAirports CSV
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+    let path = path.as_ref();
+    info!("loading geodata from {}...", path.display());
+    let raw = std::fs::read_to_string(path)?;
+    Self::parse(&raw)
+  }
+
+  /// Downloads the airports keeping a copy in `cache`.
+  /// The last good copy is used when the download fails.
+  async fn download(url: &str, cache: Option<&str>) -> Result<Self, Box<dyn Error>> {
+    info!("loading geodata from {url}...");
     let t1 = Utc::now();
-    let raw = reqwest::get(OURAIRPORTS_URL).await?.text().await?;
-    info!("geodata loaded in {}s", seconds_since(t1));
+    let res = fetch(url).await;
+    let raw = match (res, cache) {
+      (Ok(raw), _) => {
+        info!("geodata loaded in {}s", seconds_since(t1));
+        raw
+      }
+      (Err(err), Some(cache)) => {
+        warn!("error loading geodata: {err}, falling back to {cache}");
+        return Self::open(cache);
+      }
+      (Err(err), None) => return Err(err),
+    };
+
+    let geo = Self::parse(&raw)?;
+    if let Some(cache) = cache {
+      // the copy is only updated once the data is known to be good
+      if let Err(err) = write_cache(Path::new(cache), &raw) {
+        warn!("error writing geodata cache {cache}: {err}");
+      }
+    }
+    Ok(geo)
+  }
+
+  /// Loads the runways from a local OurAirports runways.csv
+  pub fn open_runways<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Box<dyn Error>> {
+    let path = path.as_ref();
+    info!("loading runways from {}...", path.display());
+    let raw = std::fs::read_to_string(path)?;
+    self.set_runways(parse_runways(&raw)?);
+    Ok(())
+  }
+
+  pub fn set_runways(&mut self, runways: Vec<Runway>) {
+    self.runways.clear();
+    for runway in runways {
+      self
+        .runways
+        .entry(runway.airport_ident.clone())
+        .or_default()
+        .push(runway);
+    }
+    info!("{} airports with runways loaded", self.runways.len());
+  }
+
+  /// Parses an airport list JSON or OurAirports CSV,
+  /// the format is detected by the content
+  pub fn parse(raw: &str) -> Result<Self, Box<dyn Error>> {
     info!("parsing geodata...");
     let t1 = Utc::now();
-    let airports: Vec<Airport> = serde_json::from_str(&raw)?;
+    let airports: Vec<Airport> = if raw.trim_start().starts_with('[') {
+      serde_json::from_str(raw)?
+    } else {
+      let mut rdr = csv::Reader::from_reader(raw.as_bytes());
+      rdr
+        .deserialize::<CsvAirport>()
+        .map(|res| res.map(Airport::from))
+        .collect::<Result<_, _>>()?
+    };
     info!("geodata parsed in {}s", seconds_since(t1));
     Ok(Self::new(airports))
   }
 
-  pub fn closest_airport(&self, lng: f64, lat: f64) -> Option<&Airport> {
-    self.airports.nearest_neighbor(&(lng, lat))
+  /// A handful of real airports and runways for tests
+  #[cfg(test)]
+  pub fn fixture() -> Self {
+    let mut geo = Self::parse(include_str!("airports.csv")).unwrap();
+    geo.set_runways(parse_runways(include_str!("runways.csv")).unwrap());
+    geo
+  }
+
+  /// Returns the nearest airport within `max_distance` nautical miles
+  /// of one of the `airport_types`, any type matches if the list is empty
+  pub fn nearest_airport(
+    &self,
+    lat: f64,
+    lng: f64,
+    max_distance: f64,
+    airport_types: &[String],
+  ) -> Option<&Airport> {
+    // squared chord length corresponding to the max distance
+    let max_chord = 2.0 * (max_distance / EARTH_RADIUS_NM / 2.0).min(FRAC_PI_2).sin();
+    let max_distance_2 = max_chord * max_chord;
+    self
+      .airports
+      .nearest_neighbor_iter_with_distance_2(&unit_vector(lat, lng))
+      .take_while(|(_, distance_2)| *distance_2 <= max_distance_2)
+      .map(|(arpt, _)| &arpt.data)
+      .find(|arpt| airport_types.is_empty() || airport_types.contains(&arpt.airport_type))
+  }
+
+  /// Returns the nearest airport within the limits set in the config
+  pub fn closest_airport(&self, lat: f64, lng: f64) -> Option<&Airport> {
+    self.nearest_airport(lat, lng, self.max_distance, &self.airport_types)
+  }
+
+  /// Finds the runway used by an aircraft at the position moving along
+  /// `hdg_true`, the one with the position closest to the centreline
+  /// among the runways of the airports within the configured distance
+  pub fn match_runway(&self, lat: f64, lng: f64, hdg_true: f64) -> Option<RunwayMatch<'_>> {
+    let max_chord = 2.0
+      * (self.max_distance / EARTH_RADIUS_NM / 2.0)
+        .min(FRAC_PI_2)
+        .sin();
+    self
+      .airports
+      .nearest_neighbor_iter_with_distance_2(&unit_vector(lat, lng))
+      .take_while(|(_, distance_2)| *distance_2 <= max_chord * max_chord)
+      .filter_map(|(arpt, _)| self.runways.get(&arpt.data.ident))
+      .flatten()
+      .filter_map(|runway| RunwayMatch::locate(runway, lat, lng, hdg_true))
+      .min_by(|a, b| {
+        a.centreline_offset
+          .abs()
+          .total_cmp(&b.centreline_offset.abs())
+      })
+  }
+}
+
+async fn fetch(url: &str) -> Result<String, Box<dyn Error>> {
+  let client = reqwest::Client::builder()
+    .timeout(DOWNLOAD_TIMEOUT)
+    .build()?;
+  let raw = client
+    .get(url)
+    .send()
+    .await?
+    .error_for_status()?
+    .text()
+    .await?;
+  Ok(raw)
+}
+
+fn write_cache(path: &Path, raw: &str) -> Result<(), Box<dyn Error>> {
+  let dir = path.parent().unwrap_or(Path::new("."));
+  let mut tmp = Builder::new().prefix(".geodata-").tempfile_in(dir)?;
+  std::io::Write::write_all(&mut tmp, raw.as_bytes())?;
+  tmp.persist(path)?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::TempDir;
+
+  #[test]
+  fn test_parse() -> Result<(), Box<dyn Error>> {
+    let geo = GeoData::fixture();
+    let arpt = geo.closest_airport(51.47, -0.45).unwrap();
+    assert_eq!(arpt.ident, "EGLL");
+    assert_eq!(arpt.keywords, vec!["LON", "Londres"]);
+
+    let raw = r#"[{"id": 2434, "ident": "EGLL", "type": "large_airport",
+      "name": "London Heathrow Airport", "latitude_deg": 51.4706,
+      "longitude_deg": -0.461941, "elevation_ft": 83, "gps_code": "EGLL",
+      "iata_code": "LHR", "local_code": "", "home_link": "",
+      "wikipedia_link": "", "keywords": ["LON"]}]"#;
+    let geo = GeoData::parse(raw)?;
+    assert_eq!(geo.closest_airport(51.47, -0.46).unwrap().ident, "EGLL");
+    Ok(())
+  }
+
+  #[test]
+  fn test_angle_diff() {
+    assert_eq!(angle_diff(10.0, 350.0), 20.0);
+    assert_eq!(angle_diff(350.0, 10.0), -20.0);
+    assert_eq!(angle_diff(90.0, 85.0), 5.0);
+  }
+
+  #[tokio::test]
+  async fn test_cache_fallback() -> Result<(), Box<dyn Error>> {
+    let dir = TempDir::new()?;
+    let cache = dir.path().join("airports.csv");
+    std::fs::write(&cache, include_str!("airports.csv"))?;
+
+    let cfg = GeoDataConfig {
+      path: None,
+      url: Some("http://127.0.0.1:1/airports.json".into()),
+      cache: Some(cache.to_string_lossy().into()),
+      ..Default::default()
+    };
+    let geo = GeoData::load(&cfg).await?;
+    assert_eq!(geo.closest_airport(40.64, -73.78).unwrap().ident, "KJFK");
+
+    let cfg = GeoDataConfig { cache: None, ..cfg };
+    assert!(GeoData::load(&cfg).await.is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_nearest_airport() {
+    let geo = GeoData::fixture();
+    // the heliport is the closest one but it's filtered out
+    assert!(geo.closest_airport(51.47, -0.18).is_none());
+    let arpt = geo.nearest_airport(51.47, -0.18, 25.0, &geo.airport_types);
+    assert_eq!(arpt.unwrap().ident, "EGLC");
+    let any = geo.nearest_airport(51.47, -0.18, 5.0, &[]).unwrap();
+    assert_eq!(any.ident, "EGLW");
+
+    // Matei is just east of the antimeridian, Nadi is far to the west
+    let arpt = geo.nearest_airport(-16.7, 179.9, 100.0, &[]).unwrap();
+    assert_eq!(arpt.ident, "NFNM");
+    let arpt = geo.nearest_airport(-16.7, -179.9, 100.0, &[]).unwrap();
+    assert_eq!(arpt.ident, "NFNM");
+
+    // nothing within the limit in the middle of the Atlantic
+    assert!(geo.closest_airport(45.0, -40.0).is_none());
+    assert!(geo.nearest_airport(45.0, -40.0, 20000.0, &[]).is_some());
+  }
+
+  #[test]
+  fn test_match_runway() {
+    let geo = GeoData::fixture();
+
+    // landing on 27L about 1500 ft past the displaced threshold
+    let m = geo.match_runway(51.46493, -0.44522, 270.0).unwrap();
+    assert_eq!(m.runway.airport_ident, "EGLL");
+    assert_eq!(m.end.ident, "27L");
+    assert!((m.threshold_distance - 1500.0).abs() < 50.0);
+    assert!(m.centreline_offset.abs() < 20.0);
+    // wind from the north is from the right for westbound aircraft
+    assert!((m.crosswind(360.0, 10.0) - 10.0).abs() < 0.5);
+
+    // the same place in the opposite direction is the far end of 09R
+    let m = geo.match_runway(51.46493, -0.44522, 90.0).unwrap();
+    assert_eq!(m.end.ident, "09R");
+    assert!(m.threshold_distance > 7000.0);
+
+    // taxiway between the runways
+    assert!(geo.match_runway(51.471, -0.45, 270.0).is_none());
+    // crossing the runway
+    assert!(geo.match_runway(51.46493, -0.44522, 0.0).is_none());
+  }
+
+  #[test]
+  fn test_distance() {
+    // Heathrow to JFK is about 2990 nm
+    let d = distance_nm(51.4706, -0.461941, 40.639447, -73.779317);
+    assert!((d - 2990.0).abs() < 10.0);
+    assert!((distance_nm(0.0, 179.5, 0.0, -179.5) - 60.0).abs() < 0.1);
   }
 }
@@ -0,0 +1,174 @@
+use serde::Deserialize;
+use std::error::Error;
+
+use super::{angle_diff, FT_IN_NM};
+
+/// Runway ends more than this off the aircraft heading are not considered
+const MAX_HEADING_DIFF: f64 = 30.0;
+
+/// Allowed distance beyond the runway edges, in feet
+const CENTRELINE_MARGIN_FT: f64 = 100.0;
+
+/// Allowed distance beyond the runway ends, in feet
+const RUNWAY_END_MARGIN_FT: f64 = 1000.0;
+
+#[derive(Debug, Clone)]
+pub struct RunwayEnd {
+  pub ident: String,
+  pub lat: f64,
+  pub lng: f64,
+  pub elevation_ft: Option<i32>,
+  /// true heading of the runway from this end
+  pub heading: f64,
+  pub displaced_threshold_ft: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Runway {
+  pub airport_ident: String,
+  pub length_ft: f64,
+  pub width_ft: f64,
+  pub surface: String,
+  pub closed: bool,
+  pub le: RunwayEnd,
+  pub he: RunwayEnd,
+}
+
+/// Runway as listed in the OurAirports runways.csv
+#[derive(Debug, Deserialize)]
+struct CsvRunway {
+  airport_ident: String,
+  length_ft: Option<f64>,
+  width_ft: Option<f64>,
+  surface: String,
+  closed: u8,
+  le_ident: String,
+  le_latitude_deg: Option<f64>,
+  le_longitude_deg: Option<f64>,
+  le_elevation_ft: Option<i32>,
+  #[serde(rename = "le_heading_degT")]
+  le_heading: Option<f64>,
+  le_displaced_threshold_ft: Option<f64>,
+  he_ident: String,
+  he_latitude_deg: Option<f64>,
+  he_longitude_deg: Option<f64>,
+  he_elevation_ft: Option<i32>,
+  #[serde(rename = "he_heading_degT")]
+  he_heading: Option<f64>,
+  he_displaced_threshold_ft: Option<f64>,
+}
+
+impl CsvRunway {
+  /// Builds the runway if the positions of both ends are known
+  fn into_runway(self) -> Option<Runway> {
+    let (le_lat, le_lng) = (self.le_latitude_deg?, self.le_longitude_deg?);
+    let (he_lat, he_lng) = (self.he_latitude_deg?, self.he_longitude_deg?);
+    let (east, north) = local_offset(le_lat, le_lng, he_lat, he_lng);
+    let bearing = east.atan2(north).to_degrees().rem_euclid(360.0);
+    let length = east.hypot(north);
+
+    Some(Runway {
+      airport_ident: self.airport_ident,
+      length_ft: self.length_ft.unwrap_or(length),
+      width_ft: self.width_ft.unwrap_or_default(),
+      surface: self.surface,
+      closed: self.closed != 0,
+      le: RunwayEnd {
+        ident: self.le_ident,
+        lat: le_lat,
+        lng: le_lng,
+        elevation_ft: self.le_elevation_ft,
+        heading: self.le_heading.unwrap_or(bearing),
+        displaced_threshold_ft: self.le_displaced_threshold_ft.unwrap_or_default(),
+      },
+      he: RunwayEnd {
+        ident: self.he_ident,
+        lat: he_lat,
+        lng: he_lng,
+        elevation_ft: self.he_elevation_ft,
+        heading: self.he_heading.unwrap_or((bearing + 180.0) % 360.0),
+        displaced_threshold_ft: self.he_displaced_threshold_ft.unwrap_or_default(),
+      },
+    })
+  }
+}
+
+/// Parses the OurAirports runways.csv, runways without the positions
+/// of their ends can't be matched and are skipped
+pub fn parse_runways(raw: &str) -> Result<Vec<Runway>, Box<dyn Error>> {
+  let mut rdr = csv::Reader::from_reader(raw.as_bytes());
+  let mut runways = vec![];
+  for res in rdr.deserialize::<CsvRunway>() {
+    if let Some(runway) = res?.into_runway() {
+      runways.push(runway);
+    }
+  }
+  Ok(runways)
+}
+
+/// Runway end an aircraft is using
+#[derive(Debug, Clone)]
+pub struct RunwayMatch<'a> {
+  pub runway: &'a Runway,
+  pub end: &'a RunwayEnd,
+  /// distance from the threshold along the centreline in feet
+  pub threshold_distance: f64,
+  /// distance from the centreline in feet, positive to the right
+  pub centreline_offset: f64,
+}
+
+impl<'a> RunwayMatch<'a> {
+  /// Locates the position on the runway used in the direction of `hdg_true`
+  pub fn locate(runway: &'a Runway, lat: f64, lng: f64, hdg_true: f64) -> Option<Self> {
+    if runway.closed {
+      return None;
+    }
+    let (end, opposite) = if angle_diff(hdg_true, runway.le.heading).abs() <= MAX_HEADING_DIFF {
+      (&runway.le, &runway.he)
+    } else if angle_diff(hdg_true, runway.he.heading).abs() <= MAX_HEADING_DIFF {
+      (&runway.he, &runway.le)
+    } else {
+      return None;
+    };
+
+    // the centreline direction from the end coordinates, published
+    // headings are rounded and may be magnetic in older data
+    let (dir_east, dir_north) = local_offset(end.lat, end.lng, opposite.lat, opposite.lng);
+    let dir_len = dir_east.hypot(dir_north);
+    if dir_len == 0.0 {
+      return None;
+    }
+    let (dir_east, dir_north) = (dir_east / dir_len, dir_north / dir_len);
+
+    let (east, north) = local_offset(end.lat, end.lng, lat, lng);
+    let along = east * dir_east + north * dir_north;
+    let offset = east * dir_north - north * dir_east;
+
+    let half_width = runway.width_ft / 2.0 + CENTRELINE_MARGIN_FT;
+    let fits_along = along >= -RUNWAY_END_MARGIN_FT && along <= dir_len + RUNWAY_END_MARGIN_FT;
+    if offset.abs() > half_width || !fits_along {
+      return None;
+    }
+
+    Some(Self {
+      runway,
+      end,
+      threshold_distance: along - end.displaced_threshold_ft,
+      centreline_offset: offset,
+    })
+  }
+
+  /// Crosswind component in knots of the wind blowing from `wind_dir`,
+  /// positive from the right
+  pub fn crosswind(&self, wind_dir: f64, wind_vel: f64) -> f64 {
+    wind_vel * angle_diff(wind_dir, self.end.heading).to_radians().sin()
+  }
+}
+
+/// Returns east and north distances in feet from the first position to
+/// the second one, accurate enough within an airport
+fn local_offset(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> (f64, f64) {
+  let north = (lat2 - lat1) * 60.0 * FT_IN_NM;
+  let east = angle_diff(lng2, lng1) * 60.0 * FT_IN_NM * lat1.to_radians().cos();
+  (east, north)
+}
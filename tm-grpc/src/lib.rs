@@ -1,5 +1,6 @@
 pub mod apiconnect;
 pub mod config;
+pub mod flight;
 pub mod geodata;
 pub mod service;
 pub mod track;
@@ -1,5 +1,5 @@
 use clap::Parser;
-use log::{error, info};
+use log::{error, info, warn};
 use simplelog::{ColorChoice, Config, TermLogger, TerminalMode};
 use tm_grpc::{
   config::read_in_config,
@@ -13,6 +13,10 @@ use tonic::transport::Server;
 struct Args {
   #[arg(short, default_value = "/etc/tangomike/tm-grpc.toml")]
   config: String,
+
+  /// migrate all track files to the latest format and exit
+  #[arg(long)]
+  migrate: bool,
 }
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -33,7 +37,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
   info!("TangoMikeFoxtrot server version {}", VERSION);
   info!("using log level {}", cfg.log.level);
 
-  let res = GeoData::load().await;
+  let store = TrackStore::new(&cfg.track);
+  if args.migrate {
+    info!("migrating track files in {}...", cfg.track.folder);
+    for res in store.migrate()? {
+      let path = res.path.display();
+      match res.status {
+        Ok(status) => info!("{path}: {status}"),
+        Err(err) => warn!("{path}: {err}"),
+      }
+    }
+    return Ok(());
+  }
+
+  let res = GeoData::load(&cfg.geodata).await;
   let geo = match res {
     Err(err) => {
       error!("error loading geodata: {err}");
@@ -51,7 +68,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
   };
 
-  let store = TrackStore::new(&cfg.track);
   let svc = TrackService::new(geo, store, &cfg.api.base_uri);
   let svc = TrackServer::new(svc);
 
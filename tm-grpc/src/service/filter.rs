@@ -0,0 +1,406 @@
+use super::tangomike::{self, track_message::Union, Downsampling, TrackMessage};
+use crate::{
+  geodata::angle_diff,
+  track::{
+    entry::{TrackFileEntry, TrackPoint},
+    error::TrackFileError,
+  },
+};
+use std::collections::HashSet;
+use tonic::Status;
+
+/// Point selection and field mask requested by a client
+#[derive(Debug, Default)]
+pub struct PointFilter {
+  min_interval: u64,
+  max_points: Option<usize>,
+  downsampling: Downsampling,
+  fields: Vec<String>,
+  /// timestamp of the last point let through
+  last_ts: Option<u64>,
+  /// points selected in advance
+  selection: Selection,
+  /// points before this position have been selected in advance
+  selected_end: usize,
+}
+
+/// Points picked out of those passing the minimum interval
+#[derive(Debug, Default)]
+enum Selection {
+  #[default]
+  All,
+  /// `max` evenly spaced ones out of `count`, picked as they are seen
+  Even {
+    count: usize,
+    max: usize,
+    seen: usize,
+  },
+  /// positions picked in advance
+  Positions(HashSet<usize>),
+}
+
+impl TryFrom<Option<tangomike::TrackFilter>> for PointFilter {
+  type Error = Status;
+
+  fn try_from(value: Option<tangomike::TrackFilter>) -> Result<Self, Self::Error> {
+    let Some(value) = value else {
+      return Ok(Self::default());
+    };
+    let downsampling = value.downsampling();
+    if value.max_points == Some(0) {
+      return Err(Status::invalid_argument("max_points must be positive"));
+    }
+    for field in value.fields.iter() {
+      // fails on unknown names
+      copy_field(&Default::default(), &mut Default::default(), field)?;
+    }
+    Ok(Self {
+      min_interval: value.min_interval,
+      max_points: value.max_points.map(|max| max as usize),
+      downsampling,
+      fields: value.fields,
+      ..Default::default()
+    })
+  }
+}
+
+impl PointFilter {
+  pub fn has_max_points(&self) -> bool {
+    self.max_points.is_some()
+  }
+
+  /// Timestamp of the last point let through
+  pub fn last_ts(&self) -> Option<u64> {
+    self.last_ts
+  }
+
+  /// Continues the minimum interval from the point sent last time
+  pub fn resume(&mut self, last_ts: Option<u64>) {
+    self.last_ts = last_ts;
+  }
+
+  /// Checks the minimum interval to the previous point let through,
+  /// the points are expected in order
+  pub fn accept(&mut self, tp: &TrackPoint) -> bool {
+    match self.last_ts {
+      Some(last) if tp.ts < last.saturating_add(self.min_interval) => false,
+      _ => {
+        self.last_ts = Some(tp.ts);
+        true
+      }
+    }
+  }
+
+  /// Selects in advance the points to send out of the given entries, which
+  /// are all the entries before position `end`, applying both the minimum
+  /// interval and the maximum count. The last point accepted is always kept.
+  ///
+  /// Even downsampling only counts the points and picks them as they are
+  /// passed. Douglas-Peucker keeps the position and the coordinates of
+  /// every point, 24 bytes a point, until the selection is made.
+  pub fn select<I>(&mut self, entries: I, end: usize) -> Result<(), TrackFileError>
+  where
+    I: IntoIterator<Item = (usize, Result<TrackFileEntry, TrackFileError>)>,
+  {
+    let last_ts = self.last_ts;
+    let keep_coords = self.downsampling == Downsampling::DouglasPeucker;
+    let mut count = 0;
+    let mut positions = vec![];
+    let mut coords = vec![];
+    for (pos, entry) in entries {
+      if let TrackFileEntry::TrackPoint(tp) = entry? {
+        if self.accept(&tp) {
+          count += 1;
+          if keep_coords {
+            positions.push(pos);
+            coords.push((tp.lat, tp.lng));
+          }
+        }
+      }
+    }
+
+    self.selection = match self.max_points {
+      Some(max) if count > max && keep_coords => {
+        let keep = douglas_peucker(&coords, max);
+        Selection::Positions(keep.into_iter().map(|idx| positions[idx]).collect())
+      }
+      Some(max) if count > max => {
+        // the interval is applied again as the points are passed
+        self.last_ts = last_ts;
+        Selection::Even {
+          count,
+          max,
+          seen: 0,
+        }
+      }
+      _ => {
+        self.last_ts = last_ts;
+        Selection::All
+      }
+    };
+    self.selected_end = end;
+    Ok(())
+  }
+
+  /// Tells if the entry at the position is to be sent, touchdowns and
+  /// events always are. The points selected in advance are expected
+  /// in order.
+  pub fn passes(&mut self, pos: usize, entry: &TrackFileEntry) -> bool {
+    let TrackFileEntry::TrackPoint(tp) = entry else {
+      return true;
+    };
+    if pos >= self.selected_end {
+      return self.accept(tp);
+    }
+    if let Selection::Positions(positions) = &self.selection {
+      return positions.contains(&pos);
+    }
+    if !self.accept(tp) {
+      return false;
+    }
+    match &mut self.selection {
+      Selection::Even { count, max, seen } => {
+        *seen += 1;
+        is_even(*seen - 1, *count, *max)
+      }
+      _ => true,
+    }
+  }
+
+  /// Drops the track point fields the client hasn't asked for
+  pub fn mask(&self, point: tangomike::TrackPoint) -> tangomike::TrackPoint {
+    if self.fields.is_empty() {
+      return point;
+    }
+    let mut masked = tangomike::TrackPoint::default();
+    for field in self.fields.iter() {
+      // the names have been checked already
+      let _ = copy_field(&point, &mut masked, field);
+    }
+    masked
+  }
+
+  pub fn mask_message(&self, msg: &mut TrackMessage) {
+    if let Some(Union::Point(point)) = msg.union.as_mut() {
+      *point = self.mask(std::mem::take(point));
+    }
+  }
+}
+
+#[allow(clippy::result_large_err)]
+fn copy_field(
+  src: &tangomike::TrackPoint,
+  dst: &mut tangomike::TrackPoint,
+  field: &str,
+) -> Result<(), Status> {
+  match field {
+    "lat" => dst.lat = src.lat,
+    "lng" => dst.lng = src.lng,
+    "hdg_true" => dst.hdg_true = src.hdg_true,
+    "alt_amsl" => dst.alt_amsl = src.alt_amsl,
+    "alt_agl" => dst.alt_agl = src.alt_agl,
+    "gnd_height" => dst.gnd_height = src.gnd_height,
+    "crs" => dst.crs = src.crs,
+    "ias" => dst.ias = src.ias,
+    "tas" => dst.tas = src.tas,
+    "gs" => dst.gs = src.gs,
+    "ap_master" => dst.ap_master = src.ap_master,
+    "gear_pct" => dst.gear_pct = src.gear_pct,
+    "flaps" => dst.flaps = src.flaps,
+    "on_gnd" => dst.on_gnd = src.on_gnd,
+    "on_rwy" => dst.on_rwy = src.on_rwy,
+    "wind_vel" => dst.wind_vel = src.wind_vel,
+    "wind_dir" => dst.wind_dir = src.wind_dir,
+    "vs" => dst.vs = src.vs,
+    "pitch" => dst.pitch = src.pitch,
+    "bank" => dst.bank = src.bank,
+    "hdg_mag" => dst.hdg_mag = src.hdg_mag,
+    "g_force" => dst.g_force = src.g_force,
+    "fuel_qty" => dst.fuel_qty = src.fuel_qty,
+    "xpdr" => dst.xpdr = src.xpdr,
+    "baro" => dst.baro = src.baro,
+    "eng_running" => dst.eng_running = src.eng_running,
+    "parking_brake" => dst.parking_brake = src.parking_brake,
+    field => {
+      return Err(Status::invalid_argument(format!(
+        "unknown track point field {field}"
+      )))
+    }
+  }
+  Ok(())
+}
+
+/// Picks `max` evenly spaced indices out of `count`, including the last one
+fn even(count: usize, max: usize) -> Vec<usize> {
+  match max {
+    0 => vec![],
+    1 => vec![count - 1],
+    _ => (0..max).map(|i| i * (count - 1) / (max - 1)).collect(),
+  }
+}
+
+/// Tells if `idx` is one of the indices picked by `even`,
+/// `count` must be greater than `max`
+fn is_even(idx: usize, count: usize, max: usize) -> bool {
+  match max {
+    0 => false,
+    1 => idx == count - 1,
+    // the first pick at or after idx
+    _ => (idx * (max - 1)).div_ceil(count - 1) * (count - 1) / (max - 1) == idx,
+  }
+}
+
+/// Douglas–Peucker limited by the number of points rather than the
+/// tolerance: the segment with the farthest point is split until
+/// there are `max` points. Returns sorted indices.
+fn douglas_peucker(coords: &[(f64, f64)], max: usize) -> Vec<usize> {
+  if max < 2 {
+    return even(coords.len(), max);
+  }
+  let last = coords.len() - 1;
+  let mut keep = vec![0, last];
+  // segments with their farthest inner point and its distance
+  let mut segments: Vec<(usize, usize, usize, f64)> = vec![];
+  let split = |start: usize, end: usize, segments: &mut Vec<_>| {
+    if let Some((idx, dist)) = farthest(coords, start, end) {
+      segments.push((start, end, idx, dist));
+    }
+  };
+  split(0, last, &mut segments);
+
+  while keep.len() < max {
+    let Some(farthest) = segments
+      .iter()
+      .enumerate()
+      .max_by(|a, b| a.1 .3.total_cmp(&b.1 .3))
+      .map(|(i, _)| i)
+    else {
+      break;
+    };
+    let (start, end, idx, _) = segments.swap_remove(farthest);
+    keep.push(idx);
+    split(start, idx, &mut segments);
+    split(idx, end, &mut segments);
+  }
+  keep.sort_unstable();
+  keep
+}
+
+/// Returns the point between `start` and `end` farthest from the line
+/// connecting them, distances are planar in degrees of latitude
+fn farthest(coords: &[(f64, f64)], start: usize, end: usize) -> Option<(usize, f64)> {
+  let (lat0, lng0) = coords[start];
+  let k = lat0.to_radians().cos();
+  let project = |(lat, lng): (f64, f64)| (angle_diff(lng, lng0) * k, lat - lat0);
+  let (ex, ey) = project(coords[end]);
+  let len = ex.hypot(ey);
+
+  (start + 1..end)
+    .map(|idx| {
+      let (x, y) = project(coords[idx]);
+      let dist = if len > 0.0 {
+        (x * ey - y * ex).abs() / len
+      } else {
+        x.hypot(y)
+      };
+      (idx, dist)
+    })
+    .max_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn point(i: usize) -> TrackFileEntry {
+    TrackFileEntry::TrackPoint(TrackPoint::at(i as u64 * 1000).pos(0.0, i as f64))
+  }
+
+  #[test]
+  fn test_select() -> Result<(), TrackFileError> {
+    let mut filter = PointFilter {
+      min_interval: 2000,
+      max_points: Some(3),
+      ..Default::default()
+    };
+    filter.select((0..10).map(|i| (i, Ok(point(i)))), 10)?;
+    let passed: Vec<usize> = (0..12).filter(|&i| filter.passes(i, &point(i))).collect();
+    // points after the selected ones only follow the interval
+    assert_eq!(passed, vec![0, 4, 8, 10]);
+
+    // an interval reaching past the end of time lets only the first through
+    let mut filter = PointFilter {
+      min_interval: u64::MAX,
+      ..Default::default()
+    };
+    assert!(filter.accept(&TrackPoint::at(1000)));
+    assert!(!filter.accept(&TrackPoint::at(2000)));
+    Ok(())
+  }
+
+  #[test]
+  fn test_is_even() {
+    for (count, max) in [
+      (10, 0),
+      (10, 1),
+      (10, 2),
+      (10, 3),
+      (10, 9),
+      (1000, 7),
+      (7919, 100),
+    ] {
+      let picked: Vec<usize> = (0..count).filter(|&idx| is_even(idx, count, max)).collect();
+      assert_eq!(picked, even(count, max), "{max} of {count}");
+    }
+  }
+
+  #[test]
+  fn test_douglas_peucker() {
+    // a straight line with a corner and a small bump
+    let coords = [
+      (0.0, 0.0),
+      (0.0, 1.0),
+      (0.1, 2.0),
+      (0.0, 3.0),
+      (1.0, 3.0),
+      (2.0, 3.0),
+    ];
+    assert_eq!(douglas_peucker(&coords, 3), vec![0, 3, 5]);
+    assert_eq!(douglas_peucker(&coords, 4), vec![0, 2, 3, 5]);
+  }
+
+  #[test]
+  fn test_mask() {
+    let filter = PointFilter::try_from(Some(tangomike::TrackFilter {
+      fields: vec!["lat".into(), "lng".into(), "vs".into()],
+      ..Default::default()
+    }))
+    .unwrap();
+    let mut msg: TrackMessage = TrackFileEntry::TrackPoint(TrackPoint {
+      lat: 1.0,
+      lng: 2.0,
+      alt_amsl: 3000.0,
+      vs: Some(-500.0),
+      ..Default::default()
+    })
+    .into();
+    filter.mask_message(&mut msg);
+    let Some(Union::Point(point)) = msg.union else {
+      panic!("expected a point");
+    };
+    assert_eq!((point.lat, point.lng, point.vs), (1.0, 2.0, Some(-500.0)));
+    assert_eq!(point.alt_amsl, 0.0);
+
+    let res = PointFilter::try_from(Some(tangomike::TrackFilter {
+      fields: vec!["altitude".into()],
+      ..Default::default()
+    }));
+    assert!(res.is_err());
+
+    let res = PointFilter::try_from(Some(tangomike::TrackFilter {
+      max_points: Some(0),
+      ..Default::default()
+    }));
+    assert!(res.is_err());
+  }
+}
@@ -0,0 +1,135 @@
+use super::state::LiveEntry;
+use crate::track::{error::TrackFileError, mapped::MappedTrackFile};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Reads the entries of a track in order from a position on, following
+/// the live channel of an active flight until the upload closes it.
+///
+/// The channel must be subscribed to before the file is mapped, so that
+/// every entry is either on disk or in the channel. Those seen in both are
+/// skipped by position, and the ones missed by a lagging receiver are
+/// read from disk.
+pub struct LiveReader {
+  tf: MappedTrackFile,
+  live: Option<broadcast::Receiver<LiveEntry>>,
+  idx: usize,
+}
+
+impl LiveReader {
+  pub fn new(
+    tf: MappedTrackFile,
+    live: Option<broadcast::Receiver<LiveEntry>>,
+    idx: usize,
+  ) -> Self {
+    Self { tf, live, idx }
+  }
+
+  /// Returns the next entry with its position, None once all the entries
+  /// of a flight which isn't active any more have been read
+  pub async fn next(&mut self) -> Result<Option<LiveEntry>, TrackFileError> {
+    loop {
+      if self.idx < self.tf.count() {
+        let pos = self.idx;
+        self.idx += 1;
+        return Ok(Some((pos, self.tf.read_at(pos)?)));
+      }
+
+      let Some(rx) = self.live.as_mut() else {
+        return Ok(None);
+      };
+      match rx.recv().await {
+        // older positions have been read from disk or replaced in place
+        Ok((pos, _)) if pos < self.idx => {}
+        Ok((pos, entry)) if pos == self.idx => {
+          self.idx += 1;
+          return Ok(Some((pos, entry)));
+        }
+        // entries written before the subscription or missed by a slow
+        // viewer are caught up from disk
+        Ok(_) | Err(RecvError::Lagged(_)) => {
+          self.tf.refresh()?;
+        }
+        Err(RecvError::Closed) => {
+          self.tf.refresh()?;
+          self.live = None;
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::track::{
+    entry::{TrackFileEntry, TrackPoint},
+    trackfile::TrackFile,
+  };
+  use tempfile::NamedTempFile;
+
+  fn point(ts: u64) -> TrackFileEntry {
+    TrackFileEntry::TrackPoint(TrackPoint::at(ts).pos(0.0, ts as f64))
+  }
+
+  async fn read_all(reader: &mut LiveReader) -> Result<Vec<usize>, TrackFileError> {
+    let mut positions = vec![];
+    while let Some((pos, entry)) = reader.next().await? {
+      assert_eq!(entry.ts(), pos as u64);
+      positions.push(pos);
+    }
+    Ok(positions)
+  }
+
+  #[tokio::test]
+  async fn test_handoff() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let mut tf = TrackFile::create(temp.path(), "E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    let (tx, rx) = broadcast::channel(16);
+    tf.append(&point(0))?;
+    tf.append(&point(1))?;
+
+    // appended between the subscription and the mapping, so it's
+    // both on disk and in the channel
+    tx.send(tf.append(&point(2))?)?;
+    let mut reader = LiveReader::new(MappedTrackFile::open(temp.path())?, Some(rx), 0);
+    tx.send(tf.append(&point(3))?)?;
+    tx.send(tf.append(&point(4))?)?;
+    drop(tx);
+
+    assert_eq!(read_all(&mut reader).await?, vec![0, 1, 2, 3, 4]);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_lagged() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let mut tf = TrackFile::create(temp.path(), "E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    let (tx, rx) = broadcast::channel(2);
+    let mut reader = LiveReader::new(MappedTrackFile::open(temp.path())?, Some(rx), 0);
+    // the receiver keeps only the last two
+    for ts in 0..5 {
+      tx.send(tf.append(&point(ts))?)?;
+    }
+    drop(tx);
+
+    assert_eq!(read_all(&mut reader).await?, vec![0, 1, 2, 3, 4]);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_closed() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let mut tf = TrackFile::create(temp.path(), "E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    let (tx, rx) = broadcast::channel(16);
+    let mut reader = LiveReader::new(MappedTrackFile::open(temp.path())?, Some(rx), 0);
+    tx.send(tf.append(&point(0))?)?;
+    assert_eq!(reader.next().await?.map(|(pos, _)| pos), Some(0));
+
+    // written but never sent before the upload closed the channel
+    tf.append(&point(1))?;
+    drop(tx);
+    assert_eq!(read_all(&mut reader).await?, vec![1]);
+    assert!(reader.next().await?.is_none());
+    Ok(())
+  }
+}
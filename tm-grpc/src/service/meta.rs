@@ -9,6 +9,8 @@ pub struct FlightMeta {
   pub aircraft_title: Option<String>,
 }
 
+// Status is what the handlers return, boxing it here wouldn't help
+#[allow(clippy::result_large_err)]
 fn extract_key(meta: &MetadataMap, key: &str) -> Result<String, Status> {
   match meta.get(key) {
     Some(value) => {
@@ -1,29 +1,55 @@
+mod filter;
+mod live;
 pub mod meta;
+mod page;
 pub mod tangomike {
+  #![allow(clippy::large_enum_variant)]
   tonic::include_proto!("tangomike");
 }
 mod state;
 
 use self::{
+  filter::PointFilter,
+  live::LiveReader,
   meta::FlightMeta,
-  state::ServiceState,
+  page::TrackPager,
+  state::{LiveEntry, ServiceState},
   tangomike::{
-    track_message, track_server::Track, upload_track_stream_request::Union, ActiveFlightsResponse,
-    DownloadTrackStreamRequest, EchoResponse, NoParams, TrackMessage, TrackRequest, TrackResponse,
-    UploadTrackStreamAck, UploadTrackStreamRequest, UploadTrackStreamResponse,
+    track_message, track_server::Track, upload_track_stream_request::Union, ActiveFlight,
+    ActiveFlightsResponse, DownloadTrackStreamRequest, EchoResponse, FlightSummaryRequest,
+    FlightSummaryResponse, LandingReportRequest, LandingReportResponse, NoParams, TrackMessage,
+    TrackRequest, TrackResponse, UploadTrackStreamAck, UploadTrackStreamRequest,
+    UploadTrackStreamResponse,
   },
 };
 use crate::{
   apiconnect::ApiConnect,
+  flight::{
+    events::EventDetector,
+    landing::analyze_landings,
+    phase::{phase_before, PhaseTracker},
+    route::{RouteEvent, RouteTracker},
+    runway::{locate_takeoff, locate_touchdown},
+    summary::FlightSummary,
+    touchdown::TouchDownInference,
+  },
   geodata::GeoData,
-  track::{entry::TrackFileEntry, store::TrackStore},
+  track::{
+    archive::archive_file,
+    entry::{Event, EventKind, FlightPhase, TrackFileEntry},
+    error::TrackFileError,
+    integrity::to_hex,
+    store::TrackStore,
+    trackfile::TrackFile,
+  },
   util::proxy_requests,
 };
 use chrono::Utc;
 use log::{error, info};
-use std::{pin::Pin, sync::Arc, time::Duration};
+use std::{path::PathBuf, pin::Pin, sync::Arc, time::Duration};
 use tokio::{
   sync::{
+    broadcast,
     mpsc::{self, error::TryRecvError},
     RwLock,
   },
@@ -35,7 +61,7 @@ use tonic::{Request, Response, Status, Streaming};
 #[derive(Debug)]
 pub struct TrackService {
   geo: Arc<GeoData>,
-  store: TrackStore,
+  store: Arc<TrackStore>,
   state: Arc<RwLock<ServiceState>>,
   api: ApiConnect,
 }
@@ -45,7 +71,7 @@ impl TrackService {
     let api = ApiConnect::new(api_base_uri);
     Self {
       geo: Arc::new(geo),
-      store,
+      store: Arc::new(store),
       state: Arc::new(RwLock::new(Default::default())),
       api,
     }
@@ -59,49 +85,47 @@ impl Track for TrackService {
     Pin<Box<dyn Stream<Item = Result<UploadTrackStreamResponse, Status>> + Send + 'static>>;
   type DownloadTrackStreamStream =
     Pin<Box<dyn Stream<Item = Result<TrackMessage, Status>> + Send + 'static>>;
+  type StreamTrackStream =
+    Pin<Box<dyn Stream<Item = Result<TrackResponse, Status>> + Send + 'static>>;
 
   async fn download_track_stream(
     &self,
     request: Request<DownloadTrackStreamRequest>,
   ) -> Result<Response<Self::DownloadTrackStreamStream>, Status> {
-    let req = request.into_inner();
-    let tf = self.store.open(&req.flight_id)?;
-    let state = self.state.clone();
+    let mut req = request.into_inner();
+    let mut filter = PointFilter::try_from(req.filter.take())?;
+    // subscribing before mapping the file makes sure every entry is either
+    // on disk or in the channel, see LiveReader
+    let live = self.state.read().await.subscribe(&req.flight_id);
+    let tf = self.store.open_mapped(&req.flight_id)?;
+    if req.cursor.is_some_and(|cursor| cursor > tf.count() as u64) {
+      // the entries the client has seen are gone, repair has dropped them
+      return Err(Status::out_of_range("cursor is past the end of the track"));
+    }
 
     let output = async_stream::try_stream! {
-      let mut count = tf.count()? as usize;
-      let mut idx = 0;
-      while idx < count {
-        let entry = tf.read_at(idx)?;
-
-        let ts = match &entry {
-          TrackFileEntry::TrackPoint(tp) => tp.ts,
-          TrackFileEntry::TouchDown(td) => td.ts,
-        };
-
-        if ts > req.start_at {
-          yield entry.into();
-        }
-
-        idx += 1;
+      let idx = match req.cursor {
+        Some(cursor) => cursor as usize,
+        // entries strictly after start_at
+        None => tf.find_ts(req.start_at.saturating_add(1))?,
+      };
+      let mut phase = if idx >= tf.count() {
+        tf.header().get_phase()
+      } else {
+        phase_before(idx, |pos| tf.read_at(pos))?
+      };
+      if filter.has_max_points() {
+        // the points recorded so far are downsampled as a whole
+        let end = tf.count();
+        filter.select((idx..).zip(tf.entries_range(idx..end)), end)?;
       }
-
-      loop {
-        let new_count = tf.count()? as usize;
-        if new_count > count {
-          count = new_count;
-          while idx < count {
-            let entry = tf.read_at(idx)?;
-            yield entry.into();
-            idx += 1;
-          }
-        }
-
-        if !state.read().await.is_active(&req.flight_id) {
-          break;
+      let mut reader = LiveReader::new(tf, live, idx);
+      while let Some((pos, entry)) = reader.next().await? {
+        if filter.passes(pos, &entry) {
+          let mut msg = track_message(entry, pos, &mut phase);
+          filter.mask_message(&mut msg);
+          yield msg;
         }
-
-        sleep(Duration::from_secs(1)).await;
       }
     };
 
@@ -120,12 +144,11 @@ impl Track for TrackService {
 
     let meta: FlightMeta = request.metadata().try_into()?;
 
-    let check = self
+    match self
       .api
       .check_flight_id(&meta.flight_id, &meta.auth_token)
-      .await;
-
-    match check {
+      .await
+    {
       Ok(check) => {
         if !check {
           return Err(Status::unauthenticated("invalid flight id or auth token"));
@@ -138,9 +161,56 @@ impl Track for TrackService {
       }
     };
 
+    // the flight is marked active before the file is opened so that
+    // it can't be archived while the client is connected
+    let Some(live) = self
+      .state
+      .write()
+      .await
+      .try_add_active_flight(&meta.flight_id)
+    else {
+      return Err(Status::unavailable(
+        "the flight is being archived, try again later",
+      ));
+    };
+
     let stream = request.into_inner();
-    let mut tf = self.store.open_or_create(&meta.flight_id)?;
+    let res = self
+      .store
+      .open_or_create(&meta.flight_id)
+      .and_then(|mut tf| {
+        tf.set_aircraft(
+          &meta.atc_id,
+          meta.atc_type.as_deref().unwrap_or_default(),
+          meta.atc_flight_number.as_deref().unwrap_or_default(),
+          meta.aircraft_title.as_deref().unwrap_or_default(),
+        )?;
+        let phase = tf.get_phase()?;
+        Ok((tf, phase))
+      });
+    let (mut tf, phase) = match res {
+      Ok(res) => res,
+      Err(err) => {
+        self
+          .state
+          .write()
+          .await
+          .remove_active_flight(&meta.flight_id);
+        return Err(err.into());
+      }
+    };
     let geo = self.geo.clone();
+    let mut events = EventDetector::new(tf.last_point().cloned());
+    let mut touchdowns = TouchDownInference::new(tf.last_point().cloned());
+    let mut route = RouteTracker::new(tf.last_point());
+    let mut phases = PhaseTracker::new(phase, tf.last_point().cloned());
+    self
+      .state
+      .write()
+      .await
+      .set_phase(&meta.flight_id, phases.phase());
+    let archive_after = self.store.archive_after();
+    let store = self.store.clone();
 
     let (tx, rx) = mpsc::channel(100);
     tokio::spawn(async move { proxy_requests(stream, tx).await });
@@ -148,17 +218,11 @@ impl Track for TrackService {
     let state = self.state.clone();
 
     let output = async_stream::try_stream! {
-      state.write().await.add_active_flight(&meta.flight_id);
-
-      let msg = UploadTrackStreamResponse {
-        ack: Some(UploadTrackStreamAck {
-          request_id: 0,
-          echo_response: None
-        })
-      };
-      yield msg;
+      yield ack(0, None);
 
       let mut rx = rx;
+      let mut held = vec![];
+      let mut held_acks = vec![];
       loop {
         let res = rx.try_recv();
         match res {
@@ -174,45 +238,72 @@ impl Track for TrackService {
             if let Some(union) = msg.union {
               match union {
                 Union::TrackMessage(msg) => {
-                  let union = &msg.union;
-                  match union {
-                    Some(union) => match union {
-                      track_message::Union::Point(pt) => {
-                        if pt.on_gnd {
-                          let dep = tf.get_departure()?;
-                          if dep.is_empty() {
-                            let closest = geo.closest_airport(pt.lng, pt.lat);
-                            if let Some(arpt) = closest {
-                              tf.set_departure(&arpt.ident)?;
-                            }
-                          }
-                        }
-                      },
-                      track_message::Union::TouchDown(td) => {
-                        let arr = tf.get_arrival()?;
-                        if arr.is_empty() {
-                          let closest = geo.closest_airport(td.lng, td.lat);
-                          if let Some(arpt) = closest {
-                            tf.set_arrival(&arpt.ident)?;
-                          }
-                        }
+                  if msg.union.is_none() {
+                    error!("got an empty request message");
+                    break;
+                  }
+
+                  // events are detected by the server, the ones sent by
+                  // clients are ignored without being decoded, so that
+                  // a malformed one can't end the upload
+                  let entry = match msg.union {
+                    Some(track_message::Union::Event(_)) => None,
+                    _ => Some(TrackFileEntry::try_from(msg)?),
+                  };
+                  let mut entries = vec![];
+                  match entry {
+                    Some(TrackFileEntry::TrackPoint(ref tp)) => {
+                      // takeoffs and landings are detected once for the
+                      // route, the events, the phases and the touchdowns
+                      let route_event = route.process(tp);
+                      if let Some(event) = route_event {
+                        update_route(&mut tf, &geo, event)?;
+                      }
+                      entries.push(TrackFileEntry::TrackPoint(tp.clone()));
+                      for mut event in events.process(tp, route_event) {
+                        locate_takeoff(&geo, &mut event, tp);
+                        entries.push(TrackFileEntry::Event(event));
+                      }
+                      if let Some(phase) = phases.process(tp, route_event) {
+                        let event = Event::new(tp.ts, EventKind::Phase(phase));
+                        entries.push(TrackFileEntry::Event(event));
+                        state.write().await.set_phase(&meta.flight_id, Some(phase));
+                      }
+                      if let Some(mut td) = touchdowns.process(tp, route_event) {
+                        // the confirmed touchdown goes before the entries
+                        // held back since the landing
+                        locate_touchdown(&geo, &mut td);
+                        held.insert(0, TrackFileEntry::TouchDown(td));
+                      }
+                    }
+                    Some(TrackFileEntry::TouchDown(mut td)) => {
+                      let pending = touchdowns.is_pending();
+                      touchdowns.client_touchdown(&td);
+                      locate_touchdown(&geo, &mut td);
+                      if pending && !touchdowns.is_pending() {
+                        // takes the place of the inferred touchdown
+                        // of the same landing
+                        held.insert(0, TrackFileEntry::TouchDown(td));
+                      } else {
+                        entries.push(TrackFileEntry::TouchDown(td));
                       }
-                    },
-                    None => {
-                      error!("got an empty request message");
-                      break;
                     }
+                    Some(TrackFileEntry::Event(_)) | None => {}
                   }
 
-                  let entry: TrackFileEntry = msg.into();
-                  tf.append(&entry)?;
-                  let msg = UploadTrackStreamResponse {
-                    ack: Some(UploadTrackStreamAck {
-                      request_id,
-                      echo_response: None
-                    })
-                  };
-                  yield msg;
+                  // entries are held back while an inferred touchdown waits
+                  // for confirmation, so that it's written in time order,
+                  // and so are the acks of the requests they came with
+                  held.append(&mut entries);
+                  held_acks.push(request_id);
+                  if !touchdowns.is_pending() {
+                    for entry in held.drain(..) {
+                      append(&mut tf, &live, &entry)?;
+                    }
+                    for request_id in held_acks.drain(..) {
+                      yield ack(request_id, None);
+                    }
+                  }
                 },
                 Union::EchoRequest(req) => {
                   let client_ts = req.timestamp_us;
@@ -221,13 +312,7 @@ impl Track for TrackService {
                     client_timestamp_us: client_ts,
                     server_timestamp_us: server_ts,
                   };
-                  let msg = UploadTrackStreamResponse {
-                    ack: Some(UploadTrackStreamAck {
-                      request_id,
-                      echo_response: Some(resp)
-                    })
-                  };
-                  yield msg;
+                  yield ack(request_id, Some(resp));
                 },
               }
             } else {
@@ -237,7 +322,46 @@ impl Track for TrackService {
         }
       }
       info!("[{remote}] client disconnected");
+      if let Some(mut td) = touchdowns.finish() {
+        locate_touchdown(&geo, &mut td);
+        held.insert(0, TrackFileEntry::TouchDown(td));
+      }
+      for entry in held.drain(..) {
+        if let Err(err) = append(&mut tf, &live, &entry) {
+          error!("[{remote}] error writing held back entries: {err}");
+          break;
+        }
+      }
+      if let Some(event) = route.finish() {
+        if let Err(err) = update_route(&mut tf, &geo, event) {
+          error!("[{remote}] error updating arrival: {err}");
+        }
+      }
+      if let Err(err) = tf.sync() {
+        error!("[{remote}] error syncing track file: {err}");
+      }
+      if let Ok(Some(digest)) = tf.get_header().map(|h| h.digest()) {
+        info!("[{remote}] track digest {}", to_hex(&digest));
+      }
+      let path = tf.path().to_path_buf();
+      let flight_id = meta.flight_id.clone();
+      let res = tokio::task::spawn_blocking(move || {
+        tf.entries()
+          .and_then(FlightSummary::compute)
+          .and_then(|summary| store.save_summary(&flight_id, &summary))
+      })
+      .await;
+      match res {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => error!("[{remote}] error saving flight summary: {err}"),
+        Err(err) => error!("[{remote}] error saving flight summary: {err}"),
+      }
       state.write().await.remove_active_flight(&meta.flight_id);
+      // closes the live channel of the viewers
+      drop(live);
+      if let Some(delay) = archive_after {
+        tokio::spawn(archive_later(state.clone(), meta.flight_id.clone(), path, delay));
+      }
     };
 
     Ok(Response::new(
@@ -249,45 +373,417 @@ impl Track for TrackService {
     &self,
     _: Request<NoParams>,
   ) -> Result<Response<ActiveFlightsResponse>, Status> {
-    let flight_ids: Vec<String> = self
-      .state
-      .write()
-      .await
+    let state = self.state.read().await;
+    let flight_ids: Vec<String> = state.active_flights.keys().cloned().collect();
+    let flights = state
       .active_flights
       .iter()
-      .cloned()
+      .map(|(flight_id, active)| {
+        let mut flight = ActiveFlight {
+          flight_id: flight_id.clone(),
+          ..Default::default()
+        };
+        if let Some(phase) = active.phase {
+          flight.set_phase(phase.into());
+        }
+        flight
+      })
       .collect();
-    Ok(Response::new(ActiveFlightsResponse { flight_ids }))
+    Ok(Response::new(ActiveFlightsResponse {
+      flight_ids,
+      flights,
+    }))
+  }
+
+  async fn get_flight_summary(
+    &self,
+    request: Request<FlightSummaryRequest>,
+  ) -> Result<Response<FlightSummaryResponse>, Status> {
+    let req = request.into_inner();
+    // the stored summary of a resumed flight is outdated until it closes
+    let active = self.state.read().await.is_active(&req.flight_id);
+    let stored = if active {
+      None
+    } else {
+      self.store.load_summary(&req.flight_id)?
+    };
+    let summary = match stored {
+      Some(summary) => summary,
+      None => {
+        let tf = self.store.open_mapped(&req.flight_id)?;
+        let summary = tokio::task::spawn_blocking(move || FlightSummary::compute(tf.entries()))
+          .await
+          .map_err(|err| Status::internal(err.to_string()))??;
+        // flights recorded before summaries were introduced
+        if !active {
+          self.store.save_summary(&req.flight_id, &summary)?;
+        }
+        summary
+      }
+    };
+    Ok(Response::new(FlightSummaryResponse {
+      flight_id: req.flight_id,
+      summary: Some(summary.into()),
+    }))
+  }
+
+  async fn get_landing_report(
+    &self,
+    request: Request<LandingReportRequest>,
+  ) -> Result<Response<LandingReportResponse>, Status> {
+    let req = request.into_inner();
+    let tf = self.store.open_mapped(&req.flight_id)?;
+    let flight_id = tf.header().get_flight_id();
+    let landings = tokio::task::spawn_blocking(move || analyze_landings(tf.entries()))
+      .await
+      .map_err(|err| Status::internal(err.to_string()))??;
+    Ok(Response::new(LandingReportResponse {
+      flight_id,
+      landings: landings.into_iter().map(|l| l.into()).collect(),
+    }))
   }
 
   async fn get_track(
     &self,
     request: Request<TrackRequest>,
   ) -> Result<Response<TrackResponse>, Status> {
-    let req = request.into_inner();
-    let tf = self.store.open(&req.flight_id)?;
+    let mut req = request.into_inner();
+    let tf = self.store.open_mapped(&req.flight_id)?;
+    let mut pager = TrackPager::page(&tf, &mut req)?;
+    Ok(Response::new(pager.next_page()?))
+  }
+
+  async fn stream_track(
+    &self,
+    request: Request<TrackRequest>,
+  ) -> Result<Response<Self::StreamTrackStream>, Status> {
+    let mut req = request.into_inner();
+    let tf = self.store.open_mapped(&req.flight_id)?;
+
+    let output = async_stream::try_stream! {
+      let mut pager = TrackPager::stream(&tf, &mut req)?;
+      loop {
+        yield pager.next_page()?;
+        if pager.is_done() {
+          break;
+        }
+      }
+    };
 
-    let entries = tf.read_all()?;
-    let mut points = vec![];
-    let mut touchdowns = vec![];
+    Ok(Response::new(Box::pin(output) as Self::StreamTrackStream))
+  }
+}
 
-    for entry in entries {
-      match entry {
-        TrackFileEntry::TrackPoint(pt) => points.push(pt),
-        TrackFileEntry::TouchDown(td) => touchdowns.push(td),
+/// Updates the departure, the arrival and the intermediate landings.
+/// The departure is set by the first takeoff unless the flight has started
+/// in the air, a full stop landing sets the arrival which becomes
+/// an intermediate landing once the aircraft takes off again.
+fn update_route(
+  tf: &mut TrackFile,
+  geo: &GeoData,
+  event: RouteEvent,
+) -> Result<(), TrackFileError> {
+  let closest = |lat, lng| geo.closest_airport(lat, lng).map(|arpt| arpt.ident.clone());
+  match event {
+    RouteEvent::Takeoff { lat, lng } => {
+      let arr = tf.get_arrival()?;
+      if !arr.is_empty() {
+        tf.add_landing(&arr)?;
+        tf.set_arrival("")?;
+      } else if tf.get_departure()?.is_empty() {
+        if let Some(ident) = closest(lat, lng) {
+          tf.set_departure(&ident)?;
+        }
+      }
+    }
+    RouteEvent::TouchAndGo { lat, lng } => {
+      if let Some(ident) = closest(lat, lng) {
+        tf.add_landing(&ident)?;
       }
     }
+    RouteEvent::FullStop { lat, lng } => {
+      if let Some(ident) = closest(lat, lng) {
+        tf.set_arrival(&ident)?;
+      }
+    }
+    RouteEvent::TakeoffRoll | RouteEvent::RejectedTakeoff | RouteEvent::TouchDown => {}
+  }
+  Ok(())
+}
+
+/// Converts the entry at position `seq` to a message carrying the phase
+/// of the flight, following the phase events
+fn track_message(
+  entry: TrackFileEntry,
+  seq: usize,
+  phase: &mut Option<FlightPhase>,
+) -> TrackMessage {
+  if let TrackFileEntry::Event(Event {
+    kind: EventKind::Phase(p),
+    ..
+  }) = entry
+  {
+    *phase = Some(p);
+  }
+  let mut msg: TrackMessage = entry.into();
+  msg.seq = seq as u64;
+  if let Some(phase) = phase {
+    msg.set_phase((*phase).into());
+  }
+  msg
+}
+
+fn ack(request_id: u64, echo_response: Option<EchoResponse>) -> UploadTrackStreamResponse {
+  UploadTrackStreamResponse {
+    ack: Some(UploadTrackStreamAck {
+      request_id,
+      echo_response,
+    }),
+  }
+}
+
+/// Appends the entry to the track file and publishes it to live viewers
+fn append(
+  tf: &mut TrackFile,
+  live: &broadcast::Sender<LiveEntry>,
+  entry: &TrackFileEntry,
+) -> Result<(), TrackFileError> {
+  let written = tf.append(entry)?;
+  // having no viewers is fine
+  let _ = live.send(written);
+  Ok(())
+}
+
+/// Converts the track file of a finished flight into the archival layout
+/// unless the flight is resumed within `delay`
+async fn archive_later(
+  state: Arc<RwLock<ServiceState>>,
+  flight_id: String,
+  path: PathBuf,
+  delay: Duration,
+) {
+  sleep(delay).await;
+  if !state.write().await.start_archiving(&flight_id) {
+    return;
+  }
+
+  let res = tokio::task::spawn_blocking(move || archive_file(path)).await;
+  match res {
+    Ok(Ok(status)) => info!("track file {flight_id} {status}"),
+    Ok(Err(err)) => error!("error archiving track file {flight_id}: {err}"),
+    Err(err) => error!("error archiving track file {flight_id}: {err}"),
+  }
+  state.write().await.finish_archiving(&flight_id);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::page::MAX_PAGE;
+  use super::*;
+  use crate::{config::TrackConfig, track::entry::TrackPoint};
+  use tempfile::TempDir;
+  use tokio_stream::StreamExt;
+
+  const FLIGHT_ID: &str = "E2B8A9FF-123B-49AB-B330-44CEAB68D465";
+
+  /// Creates a service storing a finished flight of `count` points
+  /// a second apart, numbered by their AGL altitude
+  fn service(dir: &TempDir, count: u64) -> Result<TrackService, TrackFileError> {
+    let store = TrackStore::new(&TrackConfig {
+      folder: dir.path().to_string_lossy().into_owned(),
+      ..Default::default()
+    });
+    let mut tf = store.open_or_create(FLIGHT_ID)?;
+    for ts in 0..count {
+      tf.append(&TrackFileEntry::TrackPoint(
+        TrackPoint::at(ts * 1000).agl(ts as f64),
+      ))?;
+    }
+    Ok(TrackService::new(
+      GeoData::fixture(),
+      store,
+      "http://localhost",
+    ))
+  }
+
+  #[tokio::test]
+  async fn test_download_cursor() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = TempDir::new()?;
+    let service = service(&dir, 10)?;
+    let download = |cursor| {
+      service.download_track_stream(Request::new(DownloadTrackStreamRequest {
+        flight_id: FLIGHT_ID.into(),
+        cursor: Some(cursor),
+        ..Default::default()
+      }))
+    };
+
+    let msgs: Vec<TrackMessage> = download(7)
+      .await?
+      .into_inner()
+      .collect::<Result<_, _>>()
+      .await?;
+    let seqs: Vec<u64> = msgs.iter().map(|msg| msg.seq).collect();
+    assert_eq!(seqs, vec![7, 8, 9]);
+    assert!(download(10).await?.into_inner().next().await.is_none());
+
+    // e.g. the track has been cut by repair since the client's last message
+    let Err(status) = download(11).await else {
+      panic!("expected an error");
+    };
+    assert_eq!(status.code(), tonic::Code::OutOfRange);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_download_max_points() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = TempDir::new()?;
+    let service = service(&dir, 1000)?;
+    let msgs: Vec<TrackMessage> = service
+      .download_track_stream(Request::new(DownloadTrackStreamRequest {
+        flight_id: FLIGHT_ID.into(),
+        cursor: Some(0),
+        filter: Some(tangomike::TrackFilter {
+          max_points: Some(10),
+          ..Default::default()
+        }),
+        ..Default::default()
+      }))
+      .await?
+      .into_inner()
+      .collect::<Result<_, _>>()
+      .await?;
+    let seqs: Vec<u64> = msgs.iter().map(|msg| msg.seq).collect();
+    assert_eq!(seqs, vec![0, 111, 222, 333, 444, 555, 666, 777, 888, 999]);
+    Ok(())
+  }
+
+  fn track_request(limit: Option<u32>, max_points: Option<u32>) -> TrackRequest {
+    TrackRequest {
+      flight_id: FLIGHT_ID.into(),
+      filter: Some(tangomike::TrackFilter {
+        max_points,
+        ..Default::default()
+      }),
+      limit,
+      ..Default::default()
+    }
+  }
 
-    let header = tf.get_header()?;
+  fn numbers(points: &[tangomike::TrackPoint]) -> Vec<u64> {
+    points.iter().map(|point| point.alt_agl as u64).collect()
+  }
+
+  #[tokio::test]
+  async fn test_get_track_pages() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = TempDir::new()?;
+    let service = service(&dir, 25)?;
+    let mut req = track_request(Some(4), None);
+    req.filter.as_mut().unwrap().min_interval = 2000;
+
+    let mut pages = vec![];
+    loop {
+      let resp = service
+        .get_track(Request::new(req.clone()))
+        .await?
+        .into_inner();
+      pages.push(numbers(&resp.points));
+      if resp.next_page_token.is_empty() {
+        break;
+      }
+      req.page_token = resp.next_page_token;
+    }
+    // the interval carries over the pages
+    assert_eq!(
+      pages,
+      vec![
+        vec![0, 2, 4, 6],
+        vec![8, 10, 12, 14],
+        vec![16, 18, 20, 22],
+        vec![24],
+      ]
+    );
+
+    // the selection isn't carried by the token
+    req.filter.as_mut().unwrap().max_points = Some(5);
+    let Err(status) = service.get_track(Request::new(req)).await else {
+      panic!("expected an error");
+    };
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    Ok(())
+  }
 
-    let resp = TrackResponse {
-      flight_id: header.get_flight_id(),
-      departure: header.get_departure(),
-      arrival: header.get_arrival(),
-      points: points.into_iter().map(|p| p.into()).collect(),
-      touchdowns: touchdowns.into_iter().map(|t| t.into()).collect(),
+  #[tokio::test]
+  async fn test_get_track_max_page() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = TempDir::new()?;
+    let service = service(&dir, MAX_PAGE as u64 + 5)?;
+
+    // clients unaware of the pagination get the whole track
+    let resp = service
+      .get_track(Request::new(track_request(None, None)))
+      .await?
+      .into_inner();
+    assert_eq!(resp.points.len(), MAX_PAGE + 5);
+    assert!(resp.next_page_token.is_empty());
+
+    let resp = service
+      .get_track(Request::new(track_request(Some(u32::MAX), None)))
+      .await?
+      .into_inner();
+    assert_eq!(resp.points.len(), MAX_PAGE);
+
+    // a resumed request without a limit gets a full page
+    let mut req = track_request(None, None);
+    req.page_token = resp.next_page_token;
+    let resp = service.get_track(Request::new(req)).await?.into_inner();
+    assert_eq!(resp.points.len(), 5);
+    assert!(resp.next_page_token.is_empty());
+
+    // downsampled ranges come in a single page whatever the limit
+    let resp = service
+      .get_track(Request::new(track_request(Some(10), Some(100))))
+      .await?
+      .into_inner();
+    assert_eq!(resp.points.len(), 100);
+    assert!(resp.next_page_token.is_empty());
+
+    let Err(status) = service
+      .get_track(Request::new(track_request(None, Some(MAX_PAGE as u32 + 1))))
+      .await
+    else {
+      panic!("expected an error");
     };
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    Ok(())
+  }
 
-    Ok(Response::new(resp))
+  #[tokio::test]
+  async fn test_stream_track() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = TempDir::new()?;
+    let service = service(&dir, 2500)?;
+    let stream = |req| service.stream_track(Request::new(req));
+
+    let chunks: Vec<TrackResponse> = stream(track_request(None, None))
+      .await?
+      .into_inner()
+      .collect::<Result<_, _>>()
+      .await?;
+    let sizes: Vec<usize> = chunks.iter().map(|chunk| chunk.points.len()).collect();
+    assert_eq!(sizes, vec![1000, 1000, 500]);
+    let points: Vec<_> = chunks.into_iter().flat_map(|chunk| chunk.points).collect();
+    assert_eq!(numbers(&points), (0..2500).collect::<Vec<_>>());
+
+    // the selection is made once and sent in chunks
+    let chunks: Vec<TrackResponse> = stream(track_request(Some(40), Some(100)))
+      .await?
+      .into_inner()
+      .collect::<Result<_, _>>()
+      .await?;
+    assert_eq!(chunks.len(), 3);
+    let points: Vec<_> = chunks.into_iter().flat_map(|chunk| chunk.points).collect();
+    let numbers = numbers(&points);
+    assert_eq!(numbers.len(), 100);
+    assert_eq!((numbers[0], numbers[99]), (0, 2499));
+    Ok(())
   }
 }
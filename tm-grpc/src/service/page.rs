@@ -0,0 +1,214 @@
+use super::{
+  filter::PointFilter,
+  tangomike::{TrackRequest, TrackResponse},
+};
+use crate::track::{entry::TrackFileEntry, header::Header, mapped::MappedTrackFile};
+use tonic::Status;
+
+/// Entries in a StreamTrack chunk unless the request sets the limit
+const DEFAULT_CHUNK: usize = 1000;
+
+/// Most entries in a GetTrack page or a StreamTrack chunk, also the
+/// page size of a GetTrack request resumed without a limit
+pub const MAX_PAGE: usize = 10000;
+
+/// Position in the track carried between the pages
+#[derive(Debug, PartialEq)]
+struct PageToken {
+  next: usize,
+  end: usize,
+  last_ts: Option<u64>,
+}
+
+impl PageToken {
+  fn parse(token: &str) -> Option<Self> {
+    let mut parts = token.split('.');
+    let mut pos = || -> Option<usize> { parts.next()?.parse().ok() };
+    let (next, end) = (pos()?, pos()?);
+    let last_ts = match parts.next()? {
+      "" => None,
+      ts => Some(ts.parse().ok()?),
+    };
+    if parts.next().is_some() || next > end {
+      return None;
+    }
+    Some(Self { next, end, last_ts })
+  }
+
+  fn format(&self) -> String {
+    let last_ts = self.last_ts.map(|ts| ts.to_string()).unwrap_or_default();
+    format!("{}.{}.{last_ts}", self.next, self.end)
+  }
+}
+
+/// Reads the filtered entries of a track range page by page.
+///
+/// The range is fixed by the first request, so the pages of an active
+/// flight end where the track ended then. Downsampling covers the whole
+/// range, its selection isn't carried by the page token, so downsampled
+/// ranges can't be resumed.
+pub struct TrackPager<'a> {
+  tf: &'a MappedTrackFile,
+  filter: PointFilter,
+  next: usize,
+  end: usize,
+  limit: usize,
+}
+
+impl<'a> TrackPager<'a> {
+  /// Pager of a GetTrack request. The pages are only capped when the
+  /// client asks for them by a limit or a token, requests without either
+  /// predate the pagination and get the whole range. Downsampled ranges
+  /// are returned in a single page.
+  #[allow(clippy::result_large_err)]
+  pub fn page(tf: &'a MappedTrackFile, req: &mut TrackRequest) -> Result<Self, Status> {
+    let max_points = req.filter.as_ref().and_then(|filter| filter.max_points);
+    match max_points {
+      Some(max) if max as usize > MAX_PAGE => Err(Status::invalid_argument(format!(
+        "max_points must not exceed {MAX_PAGE}"
+      ))),
+      Some(_) => {
+        // the limit would leave the rest of the selection unreachable
+        req.limit = None;
+        Self::new(tf, req, usize::MAX)
+      }
+      None if req.limit.is_none() && req.page_token.is_empty() => Self::new(tf, req, usize::MAX),
+      None => Self::new(tf, req, MAX_PAGE),
+    }
+  }
+
+  /// Pager of a StreamTrack request, each page is a chunk of the stream
+  #[allow(clippy::result_large_err)]
+  pub fn stream(tf: &'a MappedTrackFile, req: &mut TrackRequest) -> Result<Self, Status> {
+    Self::new(tf, req, DEFAULT_CHUNK)
+  }
+
+  #[allow(clippy::result_large_err)]
+  fn new(
+    tf: &'a MappedTrackFile,
+    req: &mut TrackRequest,
+    default_limit: usize,
+  ) -> Result<Self, Status> {
+    let mut filter = PointFilter::try_from(req.filter.take())?;
+    let limit = match req.limit {
+      Some(0) => return Err(Status::invalid_argument("limit must be positive")),
+      Some(limit) => (limit as usize).min(MAX_PAGE),
+      None => default_limit,
+    };
+    if filter.has_max_points() && !req.page_token.is_empty() {
+      return Err(Status::invalid_argument(
+        "downsampled ranges can't be resumed by a page token",
+      ));
+    }
+
+    let token = if req.page_token.is_empty() {
+      let start = tf.find_ts(req.start_at)?;
+      let end = match req.end_at {
+        Some(end_at) => tf.find_ts(end_at)?,
+        None => tf.count(),
+      };
+      PageToken {
+        next: start,
+        end,
+        last_ts: None,
+      }
+    } else {
+      PageToken::parse(&req.page_token)
+        .filter(|token| token.end <= tf.count())
+        .ok_or_else(|| Status::invalid_argument("invalid page token"))?
+    };
+
+    if filter.has_max_points() {
+      // selected once as the range is never resumed
+      let entries = tf.entries_range(token.next..token.end);
+      filter.select((token.next..).zip(entries), token.end)?;
+    } else {
+      filter.resume(token.last_ts);
+    }
+
+    Ok(Self {
+      tf,
+      filter,
+      next: token.next,
+      end: token.end,
+      limit,
+    })
+  }
+
+  pub fn is_done(&self) -> bool {
+    self.next >= self.end
+  }
+
+  /// Reads up to `limit` entries passing the filter into a response
+  /// along with the flight details
+  #[allow(clippy::result_large_err)]
+  pub fn next_page(&mut self) -> Result<TrackResponse, Status> {
+    let header = self.tf.header();
+    let mut resp = track_response(header);
+    let mut count = 0;
+    while self.next < self.end && count < self.limit {
+      let pos = self.next;
+      self.next += 1;
+      let entry = self.tf.read_at(pos)?;
+      if !self.filter.passes(pos, &entry) {
+        continue;
+      }
+      count += 1;
+      match entry {
+        TrackFileEntry::TrackPoint(tp) => resp.points.push(self.filter.mask(tp.into())),
+        TrackFileEntry::TouchDown(td) => resp.touchdowns.push(td.into()),
+        TrackFileEntry::Event(ev) => resp.events.push(ev.into()),
+      }
+    }
+
+    if !self.is_done() {
+      let token = PageToken {
+        next: self.next,
+        end: self.end,
+        last_ts: self.filter.last_ts(),
+      };
+      resp.next_page_token = token.format();
+    }
+    Ok(resp)
+  }
+}
+
+fn track_response(header: &Header) -> TrackResponse {
+  TrackResponse {
+    flight_id: header.get_flight_id(),
+    departure: header.get_departure(),
+    arrival: header.get_arrival(),
+    atc_id: header.get_atc_id(),
+    atc_type: header.get_atc_type(),
+    atc_flight_number: header.get_atc_flight_number(),
+    title: header.get_title(),
+    landings: header.get_landings(),
+    ..Default::default()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_page_token() {
+    let token = PageToken {
+      next: 1010,
+      end: 5000,
+      last_ts: Some(1700000000000),
+    };
+    assert_eq!(PageToken::parse(&token.format()), Some(token));
+    assert_eq!(
+      PageToken::parse("0.10."),
+      Some(PageToken {
+        next: 0,
+        end: 10,
+        last_ts: None
+      })
+    );
+    assert_eq!(PageToken::parse("20.10."), None);
+    assert_eq!(PageToken::parse("0.10"), None);
+    assert_eq!(PageToken::parse("a.b.c"), None);
+  }
+}
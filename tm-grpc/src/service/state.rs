@@ -1,20 +1,89 @@
-use std::collections::HashSet;
+use crate::track::entry::{FlightPhase, TrackFileEntry};
+use std::collections::{HashMap, HashSet};
+use tokio::sync::broadcast;
+
+/// Number of entries a live viewer may fall behind before it has to
+/// catch up from the track file
+const LIVE_CAPACITY: usize = 1024;
+
+/// Entry appended to the track file of an active flight with its position
+pub type LiveEntry = (usize, TrackFileEntry);
+
+#[derive(Debug)]
+pub struct ActiveFlight {
+  /// current phase, if known yet
+  pub phase: Option<FlightPhase>,
+  /// entries written by the upload stream, for live viewers
+  pub live: broadcast::Sender<LiveEntry>,
+}
 
 #[derive(Debug, Default)]
 pub struct ServiceState {
-  pub active_flights: HashSet<String>,
+  pub active_flights: HashMap<String, ActiveFlight>,
+  pub archiving_flights: HashSet<String>,
 }
 
 impl ServiceState {
-  pub fn add_active_flight(&mut self, flight_id: &str) {
-    self.active_flights.insert(flight_id.into());
+  /// Marks the flight active and returns the sender of its live channel
+  pub fn add_active_flight(&mut self, flight_id: &str) -> broadcast::Sender<LiveEntry> {
+    let flight = self
+      .active_flights
+      .entry(flight_id.into())
+      .or_insert_with(|| ActiveFlight {
+        phase: None,
+        live: broadcast::channel(LIVE_CAPACITY).0,
+      });
+    flight.live.clone()
   }
 
+  /// Marks the flight active unless its track file is being archived
+  pub fn try_add_active_flight(&mut self, flight_id: &str) -> Option<broadcast::Sender<LiveEntry>> {
+    if self.is_archiving(flight_id) {
+      None
+    } else {
+      Some(self.add_active_flight(flight_id))
+    }
+  }
+
+  /// Removing the flight closes its live channel once the upload
+  /// stream drops its sender
   pub fn remove_active_flight(&mut self, flight_id: &str) {
     self.active_flights.remove(flight_id);
   }
 
   pub fn is_active(&self, flight_id: &str) -> bool {
-    self.active_flights.contains(flight_id)
+    self.active_flights.contains_key(flight_id)
+  }
+
+  pub fn set_phase(&mut self, flight_id: &str, phase: Option<FlightPhase>) {
+    if let Some(flight) = self.active_flights.get_mut(flight_id) {
+      flight.phase = phase;
+    }
+  }
+
+  /// Subscribes to the entries of an active flight, None if the flight
+  /// isn't active
+  pub fn subscribe(&self, flight_id: &str) -> Option<broadcast::Receiver<LiveEntry>> {
+    self
+      .active_flights
+      .get(flight_id)
+      .map(|f| f.live.subscribe())
+  }
+
+  /// Marks the flight as being archived unless it's active again
+  pub fn start_archiving(&mut self, flight_id: &str) -> bool {
+    if self.is_active(flight_id) {
+      false
+    } else {
+      self.archiving_flights.insert(flight_id.into())
+    }
+  }
+
+  pub fn finish_archiving(&mut self, flight_id: &str) {
+    self.archiving_flights.remove(flight_id);
+  }
+
+  pub fn is_archiving(&self, flight_id: &str) -> bool {
+    self.archiving_flights.contains(flight_id)
   }
 }
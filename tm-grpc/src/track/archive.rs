@@ -0,0 +1,429 @@
+//! Archival layout for finished flights.
+//!
+//! Archived files keep the regular header (with `FLAG_ARCHIVED` set) followed
+//! by a block index and compressed blocks of `BLOCK_LEN` entries each.
+//!
+//! ```text
+//! header | block_len: u32 | block_count: u32 | (offset: u64, size: u32) * block_count | blocks
+//! ```
+//!
+//! Within a block every encoded entry is XORed with the previous one and the
+//! result is transposed, so that the same byte of consecutive entries ends up
+//! in a run. Consecutive track points differ very little, which turns most
+//! of the block into zeroes before it's deflated. The encoding is lossless,
+//! entries keep their checksums and chain links.
+
+use super::{
+  codec::{Reader, Writer},
+  error::TrackFileError,
+  format::Format,
+  header::FLAG_ARCHIVED,
+  iter::RawEntries,
+  migrate::replace_file,
+  trackfile::TrackFile,
+};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use std::{
+  fmt::Display,
+  fs::File,
+  io::{Read, Write},
+  os::unix::prelude::FileExt,
+  path::Path,
+  sync::Mutex,
+};
+
+pub const BLOCK_LEN: usize = 1024;
+
+/// Largest block length accepted when reading, bounding the memory
+/// a decoded block takes
+const MAX_BLOCK_LEN: usize = 64 * BLOCK_LEN;
+
+const INDEX_ENTRY_SIZE: usize = 8 + 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveStatus {
+  Archived { size: u64, archived_size: u64 },
+  Unarchived,
+  Unchanged,
+}
+
+impl Display for ArchiveStatus {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ArchiveStatus::Archived {
+        size,
+        archived_size,
+      } => write!(f, "archived, {size} -> {archived_size} bytes"),
+      ArchiveStatus::Unarchived => write!(f, "unarchived"),
+      ArchiveStatus::Unchanged => write!(f, "unchanged"),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+struct BlockRef {
+  offset: u64,
+  size: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct ArchiveIndex {
+  block_len: usize,
+  blocks: Vec<BlockRef>,
+  index_end: u64,
+}
+
+impl ArchiveIndex {
+  /// Reads the index of a file holding `count` entries. The index is
+  /// checked against the count and the file length before anything
+  /// it describes is allocated.
+  pub fn read(file: &File, header_size: usize, count: usize) -> Result<Self, TrackFileError> {
+    let file_len = file.metadata()?.len();
+    let mut buf = [0; 8];
+    file.read_exact_at(&mut buf, header_size as u64)?;
+    let mut r = Reader::new(&buf, "archive index");
+    let block_len = r.u32()? as usize;
+    let block_count = r.u32()? as usize;
+    if block_len == 0 || block_len > MAX_BLOCK_LEN {
+      return Err(r.invalid("block length", block_len as u64));
+    }
+    if block_count != count.div_ceil(block_len) {
+      return Err(r.invalid("block count", block_count as u64));
+    }
+    let index_end = (header_size + 8 + block_count * INDEX_ENTRY_SIZE) as u64;
+    if index_end > file_len {
+      return Err(TrackFileError::InvalidData(format!(
+        "archive index ends at {index_end} past the end of the file at {file_len}"
+      )));
+    }
+
+    let mut buf = vec![0; block_count * INDEX_ENTRY_SIZE];
+    file.read_exact_at(&mut buf, header_size as u64 + 8)?;
+    let mut r = Reader::new(&buf, "archive index");
+    let mut blocks = Vec::with_capacity(block_count);
+    for _ in 0..block_count {
+      let block = BlockRef {
+        offset: r.u64()?,
+        size: r.u32()?,
+      };
+      let end = block.offset.checked_add(block.size as u64);
+      if block.offset < index_end || end.is_none_or(|end| end > file_len) {
+        return Err(r.invalid("block offset", block.offset));
+      }
+      blocks.push(block);
+    }
+
+    Ok(Self {
+      block_len,
+      blocks,
+      index_end,
+    })
+  }
+
+  pub fn block_len(&self) -> usize {
+    self.block_len
+  }
+
+  /// Expected length of the file, i.e. the end of the last block
+  pub fn file_len(&self) -> u64 {
+    self
+      .blocks
+      .last()
+      .map(|b| b.offset + b.size as u64)
+      .unwrap_or(self.index_end)
+  }
+
+  /// Reads and decodes the block number `idx` holding `len` entries
+  pub fn read_block(
+    &self,
+    file: &File,
+    idx: usize,
+    len: usize,
+    entry_size: usize,
+  ) -> Result<Vec<u8>, TrackFileError> {
+    let block = self
+      .blocks
+      .get(idx)
+      .ok_or(TrackFileError::IndexError(idx * self.block_len))?;
+    let mut buf = vec![0; block.size as usize];
+    file.read_exact_at(&mut buf, block.offset)?;
+    decode_block(&buf, len, entry_size)
+  }
+}
+
+/// Reads the entries of an archived file, keeping the last decoded block
+/// as sequential reads mostly hit it
+#[derive(Debug)]
+pub struct ArchiveReader {
+  index: ArchiveIndex,
+  count: usize,
+  cache: Mutex<Option<(usize, Vec<u8>)>>,
+}
+
+impl ArchiveReader {
+  pub fn open(file: &File, header_size: usize, count: usize) -> Result<Self, TrackFileError> {
+    Ok(Self {
+      index: ArchiveIndex::read(file, header_size, count)?,
+      count,
+      cache: Mutex::new(None),
+    })
+  }
+
+  /// Expected length of the file, see `ArchiveIndex::file_len`
+  pub fn file_len(&self) -> u64 {
+    self.index.file_len()
+  }
+
+  /// Reads `len` encoded entries starting at `pos`,
+  /// the range must be within the file
+  pub fn read_raw(
+    &self,
+    file: &File,
+    pos: usize,
+    len: usize,
+    entry_size: usize,
+  ) -> Result<Vec<u8>, TrackFileError> {
+    let block_len = self.index.block_len();
+    let mut buf = Vec::with_capacity(len * entry_size);
+    let mut idx = pos;
+    while idx < pos + len {
+      let block_idx = idx / block_len;
+      let mut cache = self.cache.lock().unwrap();
+      if !matches!(cache.as_ref(), Some((cached_idx, _)) if *cached_idx == block_idx) {
+        let block_count = block_len.min(self.count - block_idx * block_len);
+        let block = self
+          .index
+          .read_block(file, block_idx, block_count, entry_size)?;
+        *cache = Some((block_idx, block));
+      }
+      let (_, block) = cache.as_ref().unwrap();
+      let start = idx % block_len;
+      let end = (start + pos + len - idx).min(block.len() / entry_size);
+      buf.extend_from_slice(&block[start * entry_size..end * entry_size]);
+      idx += end - start;
+    }
+    Ok(buf)
+  }
+}
+
+fn encode_block(records: &[u8], entry_size: usize) -> Result<Vec<u8>, TrackFileError> {
+  let count = records.len() / entry_size;
+  let mut data = vec![0; records.len()];
+  for i in 0..count {
+    for j in 0..entry_size {
+      let cur = records[i * entry_size + j];
+      let prev = if i > 0 {
+        records[(i - 1) * entry_size + j]
+      } else {
+        0
+      };
+      data[j * count + i] = cur ^ prev;
+    }
+  }
+  let mut enc = DeflateEncoder::new(Vec::new(), Compression::best());
+  enc.write_all(&data)?;
+  Ok(enc.finish()?)
+}
+
+fn decode_block(block: &[u8], count: usize, entry_size: usize) -> Result<Vec<u8>, TrackFileError> {
+  let mut data = Vec::with_capacity(count * entry_size);
+  // a corrupt block inflating past the expected size is cut short
+  DeflateDecoder::new(block)
+    .take((count * entry_size) as u64 + 1)
+    .read_to_end(&mut data)?;
+  if data.len() != count * entry_size {
+    return Err(TrackFileError::InvalidData(format!(
+      "archive block holds {} bytes, expected {}",
+      data.len(),
+      count * entry_size
+    )));
+  }
+
+  let mut records = vec![0; data.len()];
+  for i in 0..count {
+    for j in 0..entry_size {
+      let prev = if i > 0 {
+        records[(i - 1) * entry_size + j]
+      } else {
+        0
+      };
+      records[i * entry_size + j] = data[j * count + i] ^ prev;
+    }
+  }
+  Ok(records)
+}
+
+/// Converts a track file into the archival layout. The file must not be
+/// written to while it's being archived.
+pub fn archive_file<P: AsRef<Path>>(path: P) -> Result<ArchiveStatus, TrackFileError> {
+  let path = path.as_ref();
+  let tf = TrackFile::open(path)?;
+  if tf.is_archived() {
+    return Ok(ArchiveStatus::Unchanged);
+  }
+  let format = tf.format();
+  if format < Format::LATEST {
+    // flags are not supported by older versions, the file has to be migrated first
+    return Err(TrackFileError::UnsupportedVersion(format.version()));
+  }
+
+  let mut header = tf.get_header()?;
+  let count = header.count() as usize;
+  let entry_size = format.entry_size();
+  let size = std::fs::metadata(path)?.len();
+
+  let mut blocks = vec![];
+  let mut pos = 0;
+  while pos < count {
+    let len = BLOCK_LEN.min(count - pos);
+    let records = tf.read_raw(pos, len)?;
+    blocks.push(encode_block(&records, entry_size)?);
+    pos += len;
+  }
+
+  header.flags |= FLAG_ARCHIVED;
+  let raw_header = format.encode_header(&header);
+
+  let mut index = Writer::default();
+  index.put_u32(BLOCK_LEN as u32);
+  index.put_u32(blocks.len() as u32);
+  let mut offset = (raw_header.len() + 8 + blocks.len() * INDEX_ENTRY_SIZE) as u64;
+  for block in blocks.iter() {
+    index.put_u64(offset);
+    index.put_u32(block.len() as u32);
+    offset += block.len() as u64;
+  }
+  let index = index.into_inner();
+
+  replace_file(path, |w| {
+    w.write_all(&raw_header)?;
+    w.write_all(&index)?;
+    for block in blocks.iter() {
+      w.write_all(block)?;
+    }
+    Ok(())
+  })?;
+
+  Ok(ArchiveStatus::Archived {
+    size,
+    archived_size: offset,
+  })
+}
+
+/// Converts an archived track file back into the append-friendly layout
+pub fn unarchive_file<P: AsRef<Path>>(path: P) -> Result<ArchiveStatus, TrackFileError> {
+  let path = path.as_ref();
+  let tf = TrackFile::open(path)?;
+  if !tf.is_archived() {
+    return Ok(ArchiveStatus::Unchanged);
+  }
+
+  let mut header = tf.get_header()?;
+  let records = tf.read_raw(0, header.count() as usize)?;
+  header.flags &= !FLAG_ARCHIVED;
+  let raw_header = tf.format().encode_header(&header);
+
+  replace_file(path, |w| {
+    w.write_all(&raw_header)?;
+    w.write_all(&records)?;
+    Ok(())
+  })?;
+
+  Ok(ArchiveStatus::Unarchived)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::track::{
+    entry::{TrackFileEntry, TrackPoint},
+    header::Header,
+    integrity::VerifyStatus,
+  };
+  use tempfile::NamedTempFile;
+
+  #[test]
+  fn test_archive() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let mut header = Header::new("E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    header.set_chained();
+    let mut tf = TrackFile::create_with_header(temp.path(), header)?;
+    for ts in 0..2500 {
+      let tp = TrackPoint {
+        ts: 1700000000000 + ts * 1000,
+        lat: 51.4668786 + ts as f64 / 10000.0,
+        lng: -0.4947472 + ts as f64 / 20000.0,
+        alt_amsl: 10000.0 + ts as f64,
+        gs: 250.0,
+        ..Default::default()
+      };
+      tf.append(&TrackFileEntry::TrackPoint(tp))?;
+    }
+    let entries = tf.read_all()?;
+    let original = std::fs::read(temp.path())?;
+    drop(tf);
+
+    let status = archive_file(temp.path())?;
+    let ArchiveStatus::Archived {
+      size,
+      archived_size,
+    } = status
+    else {
+      panic!("unexpected status {status}");
+    };
+    assert_eq!(size, original.len() as u64);
+    assert_eq!(archived_size, std::fs::metadata(temp.path())?.len());
+    assert!(archived_size < size / 4);
+
+    let mut tf = TrackFile::open(temp.path())?;
+    assert!(tf.is_archived());
+    assert_eq!(tf.count()?, 2500);
+    assert_eq!(tf.read_all()?, entries);
+    assert_eq!(tf.read_at(1500)?, entries[1500]);
+    assert_eq!(tf.read_multiple_at(1020, 10)?, entries[1020..1030]);
+    assert_eq!(tf.verify()?.status, VerifyStatus::Ok);
+    assert!(matches!(
+      tf.append(&entries[0]),
+      Err(TrackFileError::Archived)
+    ));
+    drop(tf);
+
+    assert_eq!(archive_file(temp.path())?, ArchiveStatus::Unchanged);
+    assert_eq!(unarchive_file(temp.path())?, ArchiveStatus::Unarchived);
+    assert_eq!(std::fs::read(temp.path())?, original);
+    Ok(())
+  }
+
+  #[test]
+  fn test_corrupt_index() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let mut tf = TrackFile::create(temp.path(), "E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    for ts in 0..1500 {
+      let tp = TrackPoint::at(ts * 1000).pos(51.0 + ts as f64 / 10000.0, 0.0);
+      tf.append(&TrackFileEntry::TrackPoint(tp))?;
+    }
+    let header_size = tf.format().header_size() as u64;
+    drop(tf);
+    archive_file(temp.path())?;
+    let archived = std::fs::read(temp.path())?;
+
+    // a huge block length, more blocks than the entries need, a block
+    // past the end of the file and a huge block size
+    let first_block = 8;
+    for (offset, value) in [
+      (0, u32::MAX.to_le_bytes().to_vec()),
+      (4, 3u32.to_le_bytes().to_vec()),
+      (4, u32::MAX.to_le_bytes().to_vec()),
+      (first_block, u64::MAX.to_le_bytes().to_vec()),
+      (first_block + 8, u32::MAX.to_le_bytes().to_vec()),
+    ] {
+      let mut data = archived.clone();
+      let start = (header_size + offset) as usize;
+      data[start..start + value.len()].copy_from_slice(&value);
+      std::fs::write(temp.path(), data)?;
+      let res = TrackFile::open(temp.path());
+      assert!(matches!(res, Err(TrackFileError::InvalidData(_))));
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,133 @@
+use super::error::TrackFileError;
+
+/// Little-endian encoder for the on-disk track structures
+#[derive(Debug, Default)]
+pub struct Writer {
+  buf: Vec<u8>,
+}
+
+impl Writer {
+  pub fn put_u8(&mut self, value: u8) {
+    self.buf.push(value);
+  }
+
+  pub fn put_u32(&mut self, value: u32) {
+    self.buf.extend_from_slice(&value.to_le_bytes());
+  }
+
+  pub fn put_u64(&mut self, value: u64) {
+    self.buf.extend_from_slice(&value.to_le_bytes());
+  }
+
+  pub fn put_i64(&mut self, value: i64) {
+    self.buf.extend_from_slice(&value.to_le_bytes());
+  }
+
+  pub fn put_f64(&mut self, value: f64) {
+    self.buf.extend_from_slice(&value.to_le_bytes());
+  }
+
+  pub fn put_bool(&mut self, value: bool) {
+    self.buf.push(value as u8);
+  }
+
+  pub fn put_bytes(&mut self, data: &[u8]) {
+    self.buf.extend_from_slice(data);
+  }
+
+  /// Pads the buffer with zeroes up to `len` bytes
+  pub fn pad_to(&mut self, len: usize) {
+    if self.buf.len() < len {
+      self.buf.resize(len, 0);
+    }
+  }
+
+  pub fn into_inner(self) -> Vec<u8> {
+    self.buf
+  }
+}
+
+/// Little-endian decoder for the on-disk track structures.
+/// Every read is bounds-checked and values with a restricted domain
+/// (booleans, tags) are validated instead of being transmuted.
+#[derive(Debug)]
+pub struct Reader<'a> {
+  data: &'a [u8],
+  pos: usize,
+  ident: &'static str,
+}
+
+impl<'a> Reader<'a> {
+  pub fn new(data: &'a [u8], ident: &'static str) -> Self {
+    Self {
+      data,
+      pos: 0,
+      ident,
+    }
+  }
+
+  pub fn seek(&mut self, pos: usize) {
+    self.pos = pos;
+  }
+
+  pub fn skip(&mut self, len: usize) -> Result<(), TrackFileError> {
+    self.take(len)?;
+    Ok(())
+  }
+
+  fn take(&mut self, len: usize) -> Result<&'a [u8], TrackFileError> {
+    let end = self.pos + len;
+    if end > self.data.len() {
+      Err(TrackFileError::InsufficientDataLength(
+        self.ident.to_owned(),
+        self.data.len(),
+      ))
+    } else {
+      let chunk = &self.data[self.pos..end];
+      self.pos = end;
+      Ok(chunk)
+    }
+  }
+
+  fn take_array<const N: usize>(&mut self) -> Result<[u8; N], TrackFileError> {
+    let mut arr = [0; N];
+    arr.copy_from_slice(self.take(N)?);
+    Ok(arr)
+  }
+
+  pub fn u8(&mut self) -> Result<u8, TrackFileError> {
+    Ok(self.take(1)?[0])
+  }
+
+  pub fn u32(&mut self) -> Result<u32, TrackFileError> {
+    Ok(u32::from_le_bytes(self.take_array()?))
+  }
+
+  pub fn u64(&mut self) -> Result<u64, TrackFileError> {
+    Ok(u64::from_le_bytes(self.take_array()?))
+  }
+
+  pub fn i64(&mut self) -> Result<i64, TrackFileError> {
+    Ok(i64::from_le_bytes(self.take_array()?))
+  }
+
+  pub fn f64(&mut self) -> Result<f64, TrackFileError> {
+    Ok(f64::from_le_bytes(self.take_array()?))
+  }
+
+  pub fn bool(&mut self) -> Result<bool, TrackFileError> {
+    match self.u8()? {
+      0 => Ok(false),
+      1 => Ok(true),
+      value => Err(self.invalid("bool", value as u64)),
+    }
+  }
+
+  pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], TrackFileError> {
+    self.take(len)
+  }
+
+  pub fn invalid(&self, field: &str, value: u64) -> TrackFileError {
+    TrackFileError::InvalidData(format!("invalid {field} value {value} in {}", self.ident))
+  }
+}
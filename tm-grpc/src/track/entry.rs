@@ -1,4 +1,29 @@
+use super::{
+  codec::{Reader, Writer},
+  error::TrackFileError,
+  fixedstr::FixedStr,
+};
 use crate::service::tangomike::{self, track_message::Union, TrackMessage};
+use tonic::Status;
+
+const TAG_TRACK_POINT: u8 = 0;
+const TAG_TOUCH_DOWN: u8 = 1;
+const TAG_EVENT: u8 = 2;
+
+/// Number of the optional telemetry fields of a TrackPoint, each one
+/// has a bit in the presence mask
+const TELEMETRY_FIELDS: u32 = 10;
+
+/// Number of the optional fields of a TouchDown
+const TOUCH_DOWN_FIELDS: u32 = 7;
+
+/// Number of the optional runway measurements of a TouchDown
+const RUNWAY_FIELDS: u32 = 3;
+
+/// Size of an encoded entry payload: a one byte tag followed by the
+/// largest entry (TrackPoint with the optional telemetry), shorter
+/// entries are zero-padded
+pub const PAYLOAD_SIZE: usize = 1 + 8 + 10 * 8 + 1 + 2 * 8 + 2 + 3 * 8 + 4 + 7 * 8 + 4 + 2;
 
 impl From<&TrackPoint> for haversine::Location {
   fn from(value: &TrackPoint) -> Self {
@@ -10,7 +35,6 @@ impl From<&TrackPoint> for haversine::Location {
 }
 
 #[derive(Debug, Clone, Default)]
-#[repr(C)]
 pub struct TrackPoint {
   pub ts: u64,
   pub lat: f64,
@@ -31,6 +55,20 @@ pub struct TrackPoint {
   pub wind_vel: f64,
   pub wind_dir: f64,
   pub distance: f64,
+  /// vertical speed, ft/min
+  pub vs: Option<f64>,
+  pub pitch: Option<f64>,
+  pub bank: Option<f64>,
+  pub hdg_mag: Option<f64>,
+  pub g_force: Option<f64>,
+  /// total fuel quantity, kg
+  pub fuel_qty: Option<f64>,
+  /// transponder code as four octal digits, e.g. 7000
+  pub xpdr: Option<u32>,
+  /// altimeter setting, hPa
+  pub baro: Option<f64>,
+  pub eng_running: Option<bool>,
+  pub parking_brake: Option<bool>,
 }
 
 impl From<TrackPoint> for tangomike::TrackPoint {
@@ -53,12 +91,21 @@ impl From<TrackPoint> for tangomike::TrackPoint {
       on_rwy: value.on_rwy,
       wind_vel: value.wind_vel,
       wind_dir: value.wind_dir,
+      vs: value.vs,
+      pitch: value.pitch,
+      bank: value.bank,
+      hdg_mag: value.hdg_mag,
+      g_force: value.g_force,
+      fuel_qty: value.fuel_qty,
+      xpdr: value.xpdr,
+      baro: value.baro,
+      eng_running: value.eng_running,
+      parking_brake: value.parking_brake,
     }
   }
 }
 
-#[derive(Debug, Clone)]
-#[repr(C)]
+#[derive(Debug, Clone, Default)]
 pub struct TouchDown {
   pub ts: u64,
   pub bank: f64,
@@ -68,6 +115,61 @@ pub struct TouchDown {
   pub pitch: f64,
   pub lat: f64,
   pub lng: f64,
+  /// G-force at impact
+  pub g_force: Option<f64>,
+  pub gs: Option<f64>,
+  pub ias: Option<f64>,
+  pub wind_vel: Option<f64>,
+  pub wind_dir: Option<f64>,
+  /// angle between the heading and the ground track
+  pub crab_angle: Option<f64>,
+  pub on_rwy: Option<bool>,
+  /// the touchdown was inferred by the server from the track points,
+  /// vel_nrm is an estimated vertical speed then
+  pub inferred: bool,
+  /// runway matched by the server
+  pub runway: Option<RunwayRef>,
+  /// distance from the runway threshold along the centreline in feet
+  pub threshold_distance: Option<f64>,
+  /// distance from the centreline in feet, positive to the right
+  pub centreline_offset: Option<f64>,
+  /// crosswind component in knots, positive from the right
+  pub crosswind: Option<f64>,
+}
+
+/// Runway identified by the airport and the runway end idents, e.g. EGLL 27L
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunwayRef {
+  pub airport: String,
+  pub ident: String,
+}
+
+impl std::fmt::Display for RunwayRef {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{} {}", self.airport, self.ident)
+  }
+}
+
+impl RunwayRef {
+  /// Encodes the runway as two fixed strings, empty ones for no runway
+  fn encode(runway: &Option<RunwayRef>, w: &mut Writer) {
+    let (airport, ident) = match runway {
+      Some(runway) => (runway.airport.as_str(), runway.ident.as_str()),
+      None => ("", ""),
+    };
+    FixedStr::<8>::new(airport).encode(w);
+    FixedStr::<8>::new(ident).encode(w);
+  }
+
+  fn decode(r: &mut Reader) -> Result<Option<RunwayRef>, TrackFileError> {
+    let airport: String = (&FixedStr::<8>::decode(r)?).into();
+    let ident: String = (&FixedStr::<8>::decode(r)?).into();
+    if airport.is_empty() {
+      Ok(None)
+    } else {
+      Ok(Some(RunwayRef { airport, ident }))
+    }
+  }
 }
 
 impl From<TouchDown> for tangomike::TouchDown {
@@ -80,15 +182,491 @@ impl From<TouchDown> for tangomike::TouchDown {
       pitch: value.pitch,
       lat: value.lat,
       lng: value.lng,
+      g_force: value.g_force,
+      gs: value.gs,
+      ias: value.ias,
+      wind_vel: value.wind_vel,
+      wind_dir: value.wind_dir,
+      crab_angle: value.crab_angle,
+      on_rwy: value.on_rwy,
+      inferred: value.inferred,
+      airport: value
+        .runway
+        .as_ref()
+        .map(|rwy| rwy.airport.clone())
+        .unwrap_or_default(),
+      runway: value.runway.map(|rwy| rwy.ident).unwrap_or_default(),
+      threshold_distance: value.threshold_distance,
+      centreline_offset: value.centreline_offset,
+      crosswind: value.crosswind,
+    }
+  }
+}
+
+/// Phase of the flight followed by the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlightPhase {
+  Preflight,
+  TaxiOut,
+  Takeoff,
+  Climb,
+  Cruise,
+  Descent,
+  Approach,
+  Landing,
+  TaxiIn,
+  Parked,
+}
+
+impl FlightPhase {
+  const ALL: [Self; 10] = [
+    Self::Preflight,
+    Self::TaxiOut,
+    Self::Takeoff,
+    Self::Climb,
+    Self::Cruise,
+    Self::Descent,
+    Self::Approach,
+    Self::Landing,
+    Self::TaxiIn,
+    Self::Parked,
+  ];
+
+  pub(super) fn from_code(code: i64) -> Option<Self> {
+    usize::try_from(code)
+      .ok()
+      .and_then(|idx| Self::ALL.get(idx))
+      .copied()
+  }
+}
+
+impl std::fmt::Display for FlightPhase {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let name = match self {
+      FlightPhase::Preflight => "preflight",
+      FlightPhase::TaxiOut => "taxi-out",
+      FlightPhase::Takeoff => "takeoff",
+      FlightPhase::Climb => "climb",
+      FlightPhase::Cruise => "cruise",
+      FlightPhase::Descent => "descent",
+      FlightPhase::Approach => "approach",
+      FlightPhase::Landing => "landing",
+      FlightPhase::TaxiIn => "taxi-in",
+      FlightPhase::Parked => "parked",
+    };
+    write!(f, "{name}")
+  }
+}
+
+impl From<FlightPhase> for tangomike::FlightPhase {
+  fn from(value: FlightPhase) -> Self {
+    match value {
+      FlightPhase::Preflight => Self::Preflight,
+      FlightPhase::TaxiOut => Self::TaxiOut,
+      FlightPhase::Takeoff => Self::Takeoff,
+      FlightPhase::Climb => Self::Climb,
+      FlightPhase::Cruise => Self::Cruise,
+      FlightPhase::Descent => Self::Descent,
+      FlightPhase::Approach => Self::Approach,
+      FlightPhase::Landing => Self::Landing,
+      FlightPhase::TaxiIn => Self::TaxiIn,
+      FlightPhase::Parked => Self::Parked,
+    }
+  }
+}
+
+impl TryFrom<tangomike::FlightPhase> for FlightPhase {
+  type Error = Status;
+
+  fn try_from(value: tangomike::FlightPhase) -> Result<Self, Self::Error> {
+    use tangomike::FlightPhase as Phase;
+    let phase = match value {
+      Phase::Preflight => Self::Preflight,
+      Phase::TaxiOut => Self::TaxiOut,
+      Phase::Takeoff => Self::Takeoff,
+      Phase::Climb => Self::Climb,
+      Phase::Cruise => Self::Cruise,
+      Phase::Descent => Self::Descent,
+      Phase::Approach => Self::Approach,
+      Phase::Landing => Self::Landing,
+      Phase::TaxiIn => Self::TaxiIn,
+      Phase::Parked => Self::Parked,
+      Phase::Unspecified => return Err(Status::invalid_argument("unspecified flight phase")),
+    };
+    Ok(phase)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+  /// liftoff, bounces excluded
+  Takeoff,
+  GearUp,
+  GearDown,
+  /// flaps moved to the given setting
+  Flaps(i64),
+  ApEngaged,
+  ApDisengaged,
+  RunwayEnter,
+  RunwayExit,
+  /// the flight has entered the given phase
+  Phase(FlightPhase),
+}
+
+impl std::fmt::Display for EventKind {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      EventKind::Takeoff => write!(f, "takeoff"),
+      EventKind::GearUp => write!(f, "gear up"),
+      EventKind::GearDown => write!(f, "gear down"),
+      EventKind::Flaps(value) => write!(f, "flaps {value}"),
+      EventKind::ApEngaged => write!(f, "autopilot engaged"),
+      EventKind::ApDisengaged => write!(f, "autopilot disengaged"),
+      EventKind::RunwayEnter => write!(f, "entered runway"),
+      EventKind::RunwayExit => write!(f, "left runway"),
+      EventKind::Phase(phase) => write!(f, "{phase}"),
+    }
+  }
+}
+
+/// A change in the aircraft state detected by the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+  pub ts: u64,
+  pub kind: EventKind,
+  /// runway matched by the server for takeoffs
+  pub runway: Option<RunwayRef>,
+}
+
+impl Event {
+  pub fn new(ts: u64, kind: EventKind) -> Self {
+    Self {
+      ts,
+      kind,
+      runway: None,
+    }
+  }
+
+  fn encode(&self, w: &mut Writer) {
+    let (code, value) = match self.kind {
+      EventKind::Takeoff => (0, 0),
+      EventKind::GearUp => (1, 0),
+      EventKind::GearDown => (2, 0),
+      EventKind::Flaps(value) => (3, value),
+      EventKind::ApEngaged => (4, 0),
+      EventKind::ApDisengaged => (5, 0),
+      EventKind::RunwayEnter => (6, 0),
+      EventKind::RunwayExit => (7, 0),
+      EventKind::Phase(phase) => (8, phase as i64),
+    };
+    w.put_u64(self.ts);
+    w.put_u8(code);
+    w.put_i64(value);
+    RunwayRef::encode(&self.runway, w);
+  }
+
+  fn decode(r: &mut Reader) -> Result<Self, TrackFileError> {
+    let ts = r.u64()?;
+    let code = r.u8()?;
+    let value = r.i64()?;
+    let kind = match code {
+      0 => EventKind::Takeoff,
+      1 => EventKind::GearUp,
+      2 => EventKind::GearDown,
+      3 => EventKind::Flaps(value),
+      4 => EventKind::ApEngaged,
+      5 => EventKind::ApDisengaged,
+      6 => EventKind::RunwayEnter,
+      7 => EventKind::RunwayExit,
+      8 => match FlightPhase::from_code(value) {
+        Some(phase) => EventKind::Phase(phase),
+        None => return Err(r.invalid("flight phase", value as u64)),
+      },
+      code => return Err(r.invalid("event kind", code as u64)),
+    };
+    let mut event = Self::new(ts, kind);
+    event.runway = RunwayRef::decode(r)?;
+    Ok(event)
+  }
+}
+
+impl From<Event> for tangomike::FlightEvent {
+  fn from(value: Event) -> Self {
+    use tangomike::EventKind as Kind;
+    let (kind, arg) = match value.kind {
+      EventKind::Takeoff => (Kind::Takeoff, 0),
+      EventKind::GearUp => (Kind::GearUp, 0),
+      EventKind::GearDown => (Kind::GearDown, 0),
+      EventKind::Flaps(value) => (Kind::Flaps, value),
+      EventKind::ApEngaged => (Kind::ApEngaged, 0),
+      EventKind::ApDisengaged => (Kind::ApDisengaged, 0),
+      EventKind::RunwayEnter => (Kind::RunwayEnter, 0),
+      EventKind::RunwayExit => (Kind::RunwayExit, 0),
+      EventKind::Phase(phase) => (Kind::Phase, tangomike::FlightPhase::from(phase) as i64),
+    };
+    let runway = value.runway.unwrap_or_default();
+    Self {
+      ts: value.ts,
+      kind: kind as i32,
+      value: arg,
+      airport: runway.airport,
+      runway: runway.ident,
     }
   }
 }
 
 #[derive(Debug, Clone)]
-#[repr(C)]
 pub enum TrackFileEntry {
   TrackPoint(TrackPoint),
   TouchDown(TouchDown),
+  Event(Event),
+}
+
+impl TrackPoint {
+  fn encode(&self, w: &mut Writer) {
+    w.put_u64(self.ts);
+    w.put_f64(self.lat);
+    w.put_f64(self.lng);
+    w.put_f64(self.hdg_true);
+    w.put_f64(self.alt_amsl);
+    w.put_f64(self.alt_agl);
+    w.put_f64(self.gnd_height);
+    w.put_f64(self.crs);
+    w.put_f64(self.ias);
+    w.put_f64(self.tas);
+    w.put_f64(self.gs);
+    w.put_bool(self.ap_master);
+    w.put_i64(self.gear_pct);
+    w.put_i64(self.flaps);
+    w.put_bool(self.on_gnd);
+    w.put_bool(self.on_rwy);
+    w.put_f64(self.wind_vel);
+    w.put_f64(self.wind_dir);
+    w.put_f64(self.distance);
+    self.encode_telemetry(w);
+  }
+
+  fn encode_telemetry(&self, w: &mut Writer) {
+    let present = [
+      self.vs.is_some(),
+      self.pitch.is_some(),
+      self.bank.is_some(),
+      self.hdg_mag.is_some(),
+      self.g_force.is_some(),
+      self.fuel_qty.is_some(),
+      self.baro.is_some(),
+      self.xpdr.is_some(),
+      self.eng_running.is_some(),
+      self.parking_brake.is_some(),
+    ];
+    let mask = present
+      .iter()
+      .enumerate()
+      .fold(0, |mask, (bit, present)| mask | (*present as u32) << bit);
+    w.put_u32(mask);
+    w.put_f64(self.vs.unwrap_or_default());
+    w.put_f64(self.pitch.unwrap_or_default());
+    w.put_f64(self.bank.unwrap_or_default());
+    w.put_f64(self.hdg_mag.unwrap_or_default());
+    w.put_f64(self.g_force.unwrap_or_default());
+    w.put_f64(self.fuel_qty.unwrap_or_default());
+    w.put_f64(self.baro.unwrap_or_default());
+    w.put_u32(self.xpdr.unwrap_or_default());
+    w.put_bool(self.eng_running.unwrap_or_default());
+    w.put_bool(self.parking_brake.unwrap_or_default());
+  }
+
+  fn decode_telemetry(&mut self, r: &mut Reader) -> Result<(), TrackFileError> {
+    let mask = r.u32()?;
+    if mask >> TELEMETRY_FIELDS != 0 {
+      return Err(r.invalid("telemetry mask", mask as u64));
+    }
+    let present = |bit: u32| mask & (1 << bit) != 0;
+    self.vs = Some(r.f64()?).filter(|_| present(0));
+    self.pitch = Some(r.f64()?).filter(|_| present(1));
+    self.bank = Some(r.f64()?).filter(|_| present(2));
+    self.hdg_mag = Some(r.f64()?).filter(|_| present(3));
+    self.g_force = Some(r.f64()?).filter(|_| present(4));
+    self.fuel_qty = Some(r.f64()?).filter(|_| present(5));
+    self.baro = Some(r.f64()?).filter(|_| present(6));
+    self.xpdr = Some(r.u32()?).filter(|_| present(7));
+    self.eng_running = Some(r.bool()?).filter(|_| present(8));
+    self.parking_brake = Some(r.bool()?).filter(|_| present(9));
+    Ok(())
+  }
+
+  fn decode(r: &mut Reader) -> Result<Self, TrackFileError> {
+    let mut tp = Self {
+      ts: r.u64()?,
+      lat: r.f64()?,
+      lng: r.f64()?,
+      hdg_true: r.f64()?,
+      alt_amsl: r.f64()?,
+      alt_agl: r.f64()?,
+      gnd_height: r.f64()?,
+      crs: r.f64()?,
+      ias: r.f64()?,
+      tas: r.f64()?,
+      gs: r.f64()?,
+      ap_master: r.bool()?,
+      gear_pct: r.i64()?,
+      flaps: r.i64()?,
+      on_gnd: r.bool()?,
+      on_rwy: r.bool()?,
+      wind_vel: r.f64()?,
+      wind_dir: r.f64()?,
+      distance: r.f64()?,
+      ..Default::default()
+    };
+    tp.decode_telemetry(r)?;
+    Ok(tp)
+  }
+}
+
+impl TouchDown {
+  fn encode(&self, w: &mut Writer) {
+    w.put_u64(self.ts);
+    w.put_f64(self.bank);
+    w.put_f64(self.hdg_mag);
+    w.put_f64(self.hdg_true);
+    w.put_f64(self.vel_nrm);
+    w.put_f64(self.pitch);
+    w.put_f64(self.lat);
+    w.put_f64(self.lng);
+    self.encode_extended(w);
+    w.put_bool(self.inferred);
+    self.encode_runway(w);
+  }
+
+  fn encode_runway(&self, w: &mut Writer) {
+    RunwayRef::encode(&self.runway, w);
+    let present = [
+      self.threshold_distance.is_some(),
+      self.centreline_offset.is_some(),
+      self.crosswind.is_some(),
+    ];
+    let mask = present
+      .iter()
+      .enumerate()
+      .fold(0, |mask, (bit, present)| mask | (*present as u32) << bit);
+    w.put_u32(mask);
+    w.put_f64(self.threshold_distance.unwrap_or_default());
+    w.put_f64(self.centreline_offset.unwrap_or_default());
+    w.put_f64(self.crosswind.unwrap_or_default());
+  }
+
+  fn decode_runway(&mut self, r: &mut Reader) -> Result<(), TrackFileError> {
+    self.runway = RunwayRef::decode(r)?;
+    let mask = r.u32()?;
+    if mask >> RUNWAY_FIELDS != 0 {
+      return Err(r.invalid("runway mask", mask as u64));
+    }
+    let present = |bit: u32| mask & (1 << bit) != 0;
+    self.threshold_distance = Some(r.f64()?).filter(|_| present(0));
+    self.centreline_offset = Some(r.f64()?).filter(|_| present(1));
+    self.crosswind = Some(r.f64()?).filter(|_| present(2));
+    Ok(())
+  }
+
+  fn encode_extended(&self, w: &mut Writer) {
+    let present = [
+      self.g_force.is_some(),
+      self.gs.is_some(),
+      self.ias.is_some(),
+      self.wind_vel.is_some(),
+      self.wind_dir.is_some(),
+      self.crab_angle.is_some(),
+      self.on_rwy.is_some(),
+    ];
+    let mask = present
+      .iter()
+      .enumerate()
+      .fold(0, |mask, (bit, present)| mask | (*present as u32) << bit);
+    w.put_u32(mask);
+    w.put_f64(self.g_force.unwrap_or_default());
+    w.put_f64(self.gs.unwrap_or_default());
+    w.put_f64(self.ias.unwrap_or_default());
+    w.put_f64(self.wind_vel.unwrap_or_default());
+    w.put_f64(self.wind_dir.unwrap_or_default());
+    w.put_f64(self.crab_angle.unwrap_or_default());
+    w.put_bool(self.on_rwy.unwrap_or_default());
+  }
+
+  fn decode_extended(&mut self, r: &mut Reader) -> Result<(), TrackFileError> {
+    let mask = r.u32()?;
+    if mask >> TOUCH_DOWN_FIELDS != 0 {
+      return Err(r.invalid("touchdown mask", mask as u64));
+    }
+    let present = |bit: u32| mask & (1 << bit) != 0;
+    self.g_force = Some(r.f64()?).filter(|_| present(0));
+    self.gs = Some(r.f64()?).filter(|_| present(1));
+    self.ias = Some(r.f64()?).filter(|_| present(2));
+    self.wind_vel = Some(r.f64()?).filter(|_| present(3));
+    self.wind_dir = Some(r.f64()?).filter(|_| present(4));
+    self.crab_angle = Some(r.f64()?).filter(|_| present(5));
+    self.on_rwy = Some(r.bool()?).filter(|_| present(6));
+    Ok(())
+  }
+
+  fn decode(r: &mut Reader) -> Result<Self, TrackFileError> {
+    let mut td = Self {
+      ts: r.u64()?,
+      bank: r.f64()?,
+      hdg_mag: r.f64()?,
+      hdg_true: r.f64()?,
+      vel_nrm: r.f64()?,
+      pitch: r.f64()?,
+      lat: r.f64()?,
+      lng: r.f64()?,
+      ..Default::default()
+    };
+    td.decode_extended(r)?;
+    td.inferred = r.bool()?;
+    td.decode_runway(r)?;
+    Ok(td)
+  }
+}
+
+impl TrackFileEntry {
+  pub fn ts(&self) -> u64 {
+    match self {
+      Self::TrackPoint(tp) => tp.ts,
+      Self::TouchDown(td) => td.ts,
+      Self::Event(ev) => ev.ts,
+    }
+  }
+
+  /// Encodes the entry payload
+  pub fn encode(&self) -> Vec<u8> {
+    let mut w = Writer::default();
+    match self {
+      Self::TrackPoint(tp) => {
+        w.put_u8(TAG_TRACK_POINT);
+        tp.encode(&mut w);
+      }
+      Self::TouchDown(td) => {
+        w.put_u8(TAG_TOUCH_DOWN);
+        td.encode(&mut w);
+      }
+      Self::Event(ev) => {
+        w.put_u8(TAG_EVENT);
+        ev.encode(&mut w);
+      }
+    }
+    w.pad_to(PAYLOAD_SIZE);
+    w.into_inner()
+  }
+
+  pub fn decode(data: &[u8]) -> Result<Self, TrackFileError> {
+    let mut r = Reader::new(data, "track entry");
+    let entry = match r.u8()? {
+      TAG_TRACK_POINT => Self::TrackPoint(TrackPoint::decode(&mut r)?),
+      TAG_TOUCH_DOWN => Self::TouchDown(TouchDown::decode(&mut r)?),
+      TAG_EVENT => Self::Event(Event::decode(&mut r)?),
+      tag => return Err(r.invalid("entry tag", tag as u64)),
+    };
+    Ok(entry)
+  }
 }
 
 impl From<TrackFileEntry> for TrackMessage {
@@ -114,7 +692,18 @@ impl From<TrackFileEntry> for TrackMessage {
           on_rwy: tp.on_rwy,
           wind_vel: tp.wind_vel,
           wind_dir: tp.wind_dir,
+          vs: tp.vs,
+          pitch: tp.pitch,
+          bank: tp.bank,
+          hdg_mag: tp.hdg_mag,
+          g_force: tp.g_force,
+          fuel_qty: tp.fuel_qty,
+          xpdr: tp.xpdr,
+          baro: tp.baro,
+          eng_running: tp.eng_running,
+          parking_brake: tp.parking_brake,
         })),
+        ..Default::default()
       },
       TrackFileEntry::TouchDown(td) => Self {
         ts: td.ts,
@@ -126,15 +715,77 @@ impl From<TrackFileEntry> for TrackMessage {
           pitch: td.pitch,
           lat: td.lat,
           lng: td.lng,
+          g_force: td.g_force,
+          gs: td.gs,
+          ias: td.ias,
+          wind_vel: td.wind_vel,
+          wind_dir: td.wind_dir,
+          crab_angle: td.crab_angle,
+          on_rwy: td.on_rwy,
+          inferred: td.inferred,
+          airport: td
+            .runway
+            .as_ref()
+            .map(|rwy| rwy.airport.clone())
+            .unwrap_or_default(),
+          runway: td.runway.map(|rwy| rwy.ident).unwrap_or_default(),
+          threshold_distance: td.threshold_distance,
+          centreline_offset: td.centreline_offset,
+          crosswind: td.crosswind,
         })),
+        ..Default::default()
+      },
+      TrackFileEntry::Event(ev) => Self {
+        ts: ev.ts,
+        union: Some(Union::Event(ev.into())),
+        ..Default::default()
       },
     }
   }
 }
 
-impl From<TrackMessage> for TrackFileEntry {
-  fn from(value: TrackMessage) -> Self {
-    match value.union.unwrap() {
+impl TryFrom<tangomike::FlightEvent> for Event {
+  type Error = Status;
+
+  fn try_from(value: tangomike::FlightEvent) -> Result<Self, Self::Error> {
+    use tangomike::EventKind as Kind;
+    let kind = match Kind::from_i32(value.kind) {
+      Some(Kind::Takeoff) => EventKind::Takeoff,
+      Some(Kind::GearUp) => EventKind::GearUp,
+      Some(Kind::GearDown) => EventKind::GearDown,
+      Some(Kind::Flaps) => EventKind::Flaps(value.value),
+      Some(Kind::ApEngaged) => EventKind::ApEngaged,
+      Some(Kind::ApDisengaged) => EventKind::ApDisengaged,
+      Some(Kind::RunwayEnter) => EventKind::RunwayEnter,
+      Some(Kind::RunwayExit) => EventKind::RunwayExit,
+      Some(Kind::Phase) => {
+        let phase = i32::try_from(value.value)
+          .ok()
+          .and_then(tangomike::FlightPhase::from_i32)
+          .ok_or_else(|| {
+            Status::invalid_argument(format!("invalid flight phase {}", value.value))
+          })?;
+        EventKind::Phase(phase.try_into()?)
+      }
+      Some(Kind::Unspecified) | None => {
+        return Err(Status::invalid_argument(format!(
+          "invalid event kind {}",
+          value.kind
+        )))
+      }
+    };
+    Ok(Self::new(value.ts, kind))
+  }
+}
+
+impl TryFrom<TrackMessage> for TrackFileEntry {
+  type Error = Status;
+
+  fn try_from(value: TrackMessage) -> Result<Self, Self::Error> {
+    let union = value
+      .union
+      .ok_or_else(|| Status::invalid_argument("track message union is empty"))?;
+    let entry = match union {
       Union::Point(point) => Self::TrackPoint(TrackPoint {
         ts: value.ts,
         lat: point.lat,
@@ -155,6 +806,16 @@ impl From<TrackMessage> for TrackFileEntry {
         wind_vel: point.wind_vel,
         wind_dir: point.wind_dir,
         distance: 0.0,
+        vs: point.vs,
+        pitch: point.pitch,
+        bank: point.bank,
+        hdg_mag: point.hdg_mag,
+        g_force: point.g_force,
+        fuel_qty: point.fuel_qty,
+        xpdr: point.xpdr,
+        baro: point.baro,
+        eng_running: point.eng_running,
+        parking_brake: point.parking_brake,
       }),
       Union::TouchDown(td) => Self::TouchDown(TouchDown {
         ts: value.ts,
@@ -165,8 +826,19 @@ impl From<TrackMessage> for TrackFileEntry {
         pitch: td.pitch,
         lat: td.lat,
         lng: td.lng,
+        g_force: td.g_force,
+        gs: td.gs,
+        ias: td.ias,
+        wind_vel: td.wind_vel,
+        wind_dir: td.wind_dir,
+        crab_angle: td.crab_angle,
+        on_rwy: td.on_rwy,
+        // set by the server
+        ..Default::default()
       }),
-    }
+      Union::Event(ev) => Self::Event(ev.try_into()?),
+    };
+    Ok(entry)
   }
 }
 
@@ -192,9 +864,193 @@ impl PartialEq for TrackFileEntry {
           && l0.wind_dir == r0.wind_dir
           && l0.wind_vel == r0.wind_vel
           && l0.distance == r0.distance
+          && l0.vs == r0.vs
+          && l0.pitch == r0.pitch
+          && l0.bank == r0.bank
+          && l0.hdg_mag == r0.hdg_mag
+          && l0.g_force == r0.g_force
+          && l0.fuel_qty == r0.fuel_qty
+          && l0.xpdr == r0.xpdr
+          && l0.baro == r0.baro
+          && l0.eng_running == r0.eng_running
+          && l0.parking_brake == r0.parking_brake
       }
       (Self::TouchDown(_), Self::TouchDown(_)) => false,
       _ => false,
     }
   }
 }
+
+/// Builder of the track points used by the tests
+#[cfg(test)]
+impl TrackPoint {
+  /// Ground elevation of the test points, ft
+  pub const TEST_GROUND_FT: f64 = 100.0;
+
+  /// Starts a point at the given time with the other fields defaulted
+  pub fn at(ts: u64) -> Self {
+    Self {
+      ts,
+      ..Default::default()
+    }
+  }
+
+  pub fn pos(self, lat: f64, lng: f64) -> Self {
+    Self { lat, lng, ..self }
+  }
+
+  /// Sets the height above ground and the altitude matching it
+  pub fn agl(self, alt_agl: f64) -> Self {
+    Self {
+      alt_agl,
+      alt_amsl: alt_agl + Self::TEST_GROUND_FT,
+      ..self
+    }
+  }
+
+  pub fn on_gnd(self, on_gnd: bool) -> Self {
+    Self { on_gnd, ..self }
+  }
+
+  pub fn gs(self, gs: f64) -> Self {
+    Self { gs, ..self }
+  }
+
+  pub fn ias(self, ias: f64) -> Self {
+    Self { ias, ..self }
+  }
+
+  pub fn vs(self, vs: f64) -> Self {
+    Self {
+      vs: Some(vs),
+      ..self
+    }
+  }
+
+  pub fn config(self, gear_pct: i64, flaps: i64) -> Self {
+    Self {
+      gear_pct,
+      flaps,
+      ..self
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_telemetry() -> Result<(), TrackFileError> {
+    let tp = TrackPoint {
+      ts: 1000,
+      lat: 51.4668786,
+      lng: -0.4947472,
+      vs: Some(-700.0),
+      g_force: Some(1.0),
+      xpdr: Some(7000),
+      parking_brake: Some(false),
+      ..Default::default()
+    };
+    let entry = TrackFileEntry::TrackPoint(tp);
+
+    let data = entry.encode();
+    assert_eq!(data.len(), PAYLOAD_SIZE);
+    assert_eq!(TrackFileEntry::decode(&data)?, entry);
+    let TrackFileEntry::TrackPoint(decoded) = TrackFileEntry::decode(&data)? else {
+      panic!("expected a track point");
+    };
+    assert_eq!(decoded.vs, Some(-700.0));
+    assert_eq!(decoded.pitch, None);
+    assert_eq!(decoded.parking_brake, Some(false));
+    Ok(())
+  }
+
+  #[test]
+  fn test_touch_down() -> Result<(), TrackFileError> {
+    let td = TouchDown {
+      ts: 1000,
+      vel_nrm: -2.1,
+      g_force: Some(1.3),
+      gs: Some(132.0),
+      crab_angle: Some(-2.5),
+      on_rwy: Some(true),
+      ..Default::default()
+    };
+    let data = TrackFileEntry::TouchDown(td).encode();
+    assert_eq!(data.len(), PAYLOAD_SIZE);
+    let TrackFileEntry::TouchDown(decoded) = TrackFileEntry::decode(&data)? else {
+      panic!("expected a touchdown");
+    };
+    assert_eq!(decoded.vel_nrm, -2.1);
+    assert_eq!(decoded.g_force, Some(1.3));
+    assert_eq!(decoded.gs, Some(132.0));
+    assert_eq!(decoded.ias, None);
+    assert_eq!(decoded.crab_angle, Some(-2.5));
+    assert_eq!(decoded.on_rwy, Some(true));
+    Ok(())
+  }
+
+  #[test]
+  fn test_runway() -> Result<(), TrackFileError> {
+    let runway = RunwayRef {
+      airport: "EGLL".into(),
+      ident: "27L".into(),
+    };
+    let td = TouchDown {
+      ts: 1000,
+      inferred: true,
+      runway: Some(runway.clone()),
+      threshold_distance: Some(1350.0),
+      crosswind: Some(-4.5),
+      ..Default::default()
+    };
+    let data = TrackFileEntry::TouchDown(td).encode();
+    assert_eq!(data.len(), PAYLOAD_SIZE);
+    let TrackFileEntry::TouchDown(decoded) = TrackFileEntry::decode(&data)? else {
+      panic!("expected a touchdown");
+    };
+    assert_eq!(
+      decoded.runway.as_ref().map(|r| r.to_string()).as_deref(),
+      Some("EGLL 27L")
+    );
+    assert_eq!(decoded.threshold_distance, Some(1350.0));
+    assert_eq!(decoded.centreline_offset, None);
+    assert_eq!(decoded.crosswind, Some(-4.5));
+
+    let mut ev = Event::new(1000, EventKind::Takeoff);
+    ev.runway = Some(runway);
+    let data = TrackFileEntry::Event(ev.clone()).encode();
+    let TrackFileEntry::Event(decoded) = TrackFileEntry::decode(&data)? else {
+      panic!("expected an event");
+    };
+    assert_eq!(decoded, ev);
+    Ok(())
+  }
+
+  #[test]
+  fn test_event() -> Result<(), TrackFileError> {
+    let entry = TrackFileEntry::Event(Event::new(1000, EventKind::Flaps(2)));
+    let data = entry.encode();
+    let TrackFileEntry::Event(ev) = TrackFileEntry::decode(&data)? else {
+      panic!("expected an event");
+    };
+    assert_eq!(ev.ts, 1000);
+    assert_eq!(ev.kind, EventKind::Flaps(2));
+
+    let phase = TrackFileEntry::Event(Event::new(2000, EventKind::Phase(FlightPhase::TaxiIn)));
+    let TrackFileEntry::Event(ev) = TrackFileEntry::decode(&phase.encode())? else {
+      panic!("expected an event");
+    };
+    assert_eq!(ev.kind, EventKind::Phase(FlightPhase::TaxiIn));
+
+    // unknown entry tags are rejected
+    let mut data = data;
+    data[0] = 3;
+    assert!(matches!(
+      TrackFileEntry::decode(&data),
+      Err(TrackFileError::InvalidData(_))
+    ));
+    Ok(())
+  }
+}
@@ -11,6 +11,11 @@ pub enum TrackFileError {
   IndexError(usize),
   NotFound(String),
   InvalidFlightId(&'static str),
+  InvalidData(String),
+  UnsupportedVersion(u64),
+  ChecksumMismatch,
+  Archived,
+  Chained,
 }
 
 impl Display for TrackFileError {
@@ -34,6 +39,21 @@ impl Display for TrackFileError {
       TrackFileError::InvalidFlightId(err) => {
         write!(f, "FlightId is incorrect: {err}")
       }
+      TrackFileError::InvalidData(err) => {
+        write!(f, "Track file corrupted, {err}")
+      }
+      TrackFileError::UnsupportedVersion(version) => {
+        write!(f, "Unsupported track file version {version}")
+      }
+      TrackFileError::ChecksumMismatch => {
+        write!(f, "Track file corrupted, entry checksum mismatch")
+      }
+      TrackFileError::Archived => {
+        write!(f, "Track file is archived and can't be modified")
+      }
+      TrackFileError::Chained => {
+        write!(f, "Track file is chained and can't be rewritten")
+      }
     }
   }
 }
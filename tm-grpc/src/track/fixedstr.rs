@@ -1,4 +1,7 @@
-use std::cmp;
+use super::{
+  codec::{Reader, Writer},
+  error::TrackFileError,
+};
 
 #[derive(Debug, Clone)]
 pub struct FixedStr<const N: usize> {
@@ -21,26 +24,60 @@ impl<const N: usize> FixedStr<N> {
     fs
   }
 
+  /// Sets the string truncating it to N bytes, a character which
+  /// doesn't fit completely is dropped
   pub fn set(&mut self, src: &str) {
-    let max_len = cmp::min(N, src.len());
-    for (i, c) in src.char_indices() {
-      if i >= max_len {
-        break;
-      }
-      self.data[i] = c as u8;
+    let mut len = src.len().min(N);
+    while !src.is_char_boundary(len) {
+      len -= 1;
     }
-    self.len = max_len
+    self.data = [0; N];
+    self.data[..len].copy_from_slice(&src.as_bytes()[..len]);
+    self.len = len;
+  }
+
+  /// Encodes the string as a one byte length followed by N data bytes
+  pub fn encode(&self, w: &mut Writer) {
+    w.put_u8(self.len as u8);
+    w.put_bytes(&self.data);
+  }
+
+  pub fn decode(r: &mut Reader) -> Result<Self, TrackFileError> {
+    let len = r.u8()? as usize;
+    if len > N {
+      return Err(r.invalid("string length", len as u64));
+    }
+    let mut data = [0; N];
+    data.copy_from_slice(r.bytes(N)?);
+    Ok(Self { len, data })
+  }
+
+  /// Builds the string from a raw length and data pair validating the length
+  pub fn from_raw_parts(len: usize, src: &[u8]) -> Option<Self> {
+    if len > N || src.len() < N {
+      None
+    } else {
+      let mut data = [0; N];
+      data.copy_from_slice(&src[..N]);
+      Some(Self { len, data })
+    }
+  }
+
+  pub fn raw_len(&self) -> usize {
+    self.len
+  }
+
+  pub fn raw_data(&self) -> &[u8; N] {
+    &self.data
   }
 }
 
 impl<const N: usize> From<&FixedStr<N>> for String {
   fn from(value: &FixedStr<N>) -> Self {
+    // strings written by older versions could have been cut in the middle
+    // of a character, the broken tail is replaced instead of dropping the string
     let raw = &value.data[..value.len];
-    let res = String::from_utf8(raw.to_vec());
-    match res {
-      Ok(s) => s,
-      Err(_) => String::new(),
-    }
+    String::from_utf8_lossy(raw).into_owned()
   }
 }
 
@@ -49,3 +86,26 @@ impl<const N: usize> From<&str> for FixedStr<N> {
     FixedStr::new(value)
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_utf8_truncation() {
+    let s: FixedStr<8> = "EGLL".into();
+    assert_eq!(String::from(&s), "EGLL");
+
+    let s: FixedStr<8> = "Aérospatiale".into();
+    assert_eq!(s.raw_len(), 8);
+    assert_eq!(String::from(&s), "Aérospa");
+
+    // 'é' takes two bytes and doesn't fit into the last one
+    let s: FixedStr<2> = "Aé".into();
+    assert_eq!(s.raw_len(), 1);
+    assert_eq!(String::from(&s), "A");
+
+    let s: FixedStr<4> = "ДМД".into();
+    assert_eq!(String::from(&s), "ДМ");
+  }
+}
@@ -0,0 +1,113 @@
+use super::{
+  entry::{self, TrackFileEntry},
+  error::TrackFileError,
+  header::{self, Header},
+  integrity::{self, Digest},
+  legacy,
+};
+
+/// Number of bytes sufficient to decode a header of any known version
+pub const MAX_HEADER_SIZE: usize = header::HEADER_SIZE;
+
+/// On-disk layout of a track file, selected by the header version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Format {
+  /// raw copies of the in-memory structs, read only
+  V1,
+  /// explicit little-endian encoding, entries carry a crc32 and a chain link
+  V2,
+}
+
+impl Format {
+  pub const LATEST: Format = Format::V2;
+
+  pub fn from_version(version: u64) -> Result<Self, TrackFileError> {
+    match version {
+      1 => Ok(Self::V1),
+      2 => Ok(Self::V2),
+      _ => Err(TrackFileError::UnsupportedVersion(version)),
+    }
+  }
+
+  /// Detects the format of a file by its magic number and version
+  /// which are located at the same offsets in every header version
+  pub fn detect(data: &[u8]) -> Result<Self, TrackFileError> {
+    let (magic, version) = Header::peek_version(data)?;
+    if magic != header::HEADER_MAGIC_NUMBER {
+      Err(TrackFileError::InvalidMagicNumber)
+    } else {
+      Self::from_version(version)
+    }
+  }
+
+  pub fn version(&self) -> u64 {
+    match self {
+      Self::V1 => legacy::HEADER_VERSION,
+      Self::V2 => header::HEADER_VERSION,
+    }
+  }
+
+  /// Entries of this format carry an integrity trailer
+  pub fn is_sealed(&self) -> bool {
+    *self >= Self::V2
+  }
+
+  pub fn header_size(&self) -> usize {
+    match self {
+      Self::V1 => legacy::HEADER_SIZE,
+      Self::V2 => header::HEADER_SIZE,
+    }
+  }
+
+  pub fn entry_size(&self) -> usize {
+    match self {
+      Self::V1 => legacy::ENTRY_SIZE,
+      Self::V2 => entry::PAYLOAD_SIZE + integrity::TRAILER_SIZE,
+    }
+  }
+
+  pub fn decode_header(&self, data: &[u8]) -> Result<Header, TrackFileError> {
+    match self {
+      Self::V1 => legacy::decode_header(data),
+      Self::V2 => Header::decode(data),
+    }
+  }
+
+  pub fn encode_header(&self, header: &Header) -> Vec<u8> {
+    match self {
+      Self::V1 => legacy::encode_header(header),
+      Self::V2 => header.encode(),
+    }
+  }
+
+  pub fn decode_entry(&self, data: &[u8]) -> Result<TrackFileEntry, TrackFileError> {
+    match self {
+      Self::V1 => legacy::decode_entry(data),
+      Self::V2 => {
+        let (payload, _) = integrity::unseal(data)?;
+        TrackFileEntry::decode(payload)
+      }
+    }
+  }
+
+  /// Encodes the entry payload which the chain links are computed of
+  pub fn encode_payload(&self, entry: &TrackFileEntry) -> Vec<u8> {
+    match self {
+      Self::V1 => legacy::encode_entry(entry),
+      Self::V2 => entry.encode(),
+    }
+  }
+
+  pub fn encode_entry(&self, entry: &TrackFileEntry) -> Vec<u8> {
+    self.encode_linked_entry(entry, None)
+  }
+
+  /// Encodes an entry with a chain link, the link is ignored by the formats
+  /// which don't support chaining
+  pub fn encode_linked_entry(&self, entry: &TrackFileEntry, link: Option<&Digest>) -> Vec<u8> {
+    match self {
+      Self::V1 => legacy::encode_entry(entry),
+      Self::V2 => integrity::seal(entry.encode(), link),
+    }
+  }
+}
@@ -1,19 +1,63 @@
-use super::{error::TrackFileError, fixedstr::FixedStr};
+use super::{
+  codec::{Reader, Writer},
+  entry::FlightPhase,
+  error::TrackFileError,
+  fixedstr::FixedStr,
+  integrity::{chain_seed, Digest},
+};
 use chrono::Utc;
 
-const HEADER_MAGIC_NUMBER: u64 = 0xfb9cfc9b116a158e;
-const HEADER_VERSION: u64 = 1;
+pub const HEADER_MAGIC_NUMBER: u64 = 0xfb9cfc9b116a158e;
+pub const HEADER_VERSION: u64 = 2;
+
+/// Entries are linked into a hash chain, see `integrity`
+pub const FLAG_CHAINED: u32 = 1;
+
+/// Entries are stored in compressed blocks, see `archive`
+pub const FLAG_ARCHIVED: u32 = 2;
+
+/// Number of intermediate landings kept in the header
+pub const MAX_LANDINGS: usize = 16;
+
+/// Size of the encoded header: four u64 fields, the flight id and
+/// airports, flags and the chain digest, the ATC and aircraft metadata
+/// the number of intermediate landings followed by the fixed slots
+/// and the flight phase
+pub const HEADER_SIZE: usize = 4 * 8
+  + (1 + 36)
+  + 2 * (1 + 8)
+  + 4
+  + 32
+  + (1 + 16)
+  + (1 + 32)
+  + (1 + 16)
+  + (1 + 128)
+  + 1
+  + MAX_LANDINGS * (1 + 8)
+  + 1;
 
 #[derive(Debug, Clone)]
-#[repr(C)]
 pub struct Header {
   pub magic: u64,
   pub version: u64,
   pub updated_at: u64,
   pub count: u64,
-  flight_id: FixedStr<36>,
-  departure: FixedStr<8>,
-  arrival: FixedStr<8>,
+  pub(super) flight_id: FixedStr<36>,
+  pub(super) departure: FixedStr<8>,
+  pub(super) arrival: FixedStr<8>,
+  pub flags: u32,
+  /// chain digest after the last entry
+  pub(super) digest: Digest,
+  pub(super) atc_id: FixedStr<16>,
+  pub(super) atc_type: FixedStr<32>,
+  pub(super) atc_flight_number: FixedStr<16>,
+  /// aircraft title as reported by the simulator
+  pub(super) title: FixedStr<128>,
+  /// airports of the touch-and-goes and full stop landings before
+  /// the arrival, in the order of landing
+  pub(super) landings: Vec<FixedStr<8>>,
+  /// phase of the last phase event
+  pub(super) phase: Option<FlightPhase>,
 }
 
 impl Header {
@@ -26,9 +70,112 @@ impl Header {
       flight_id: flight_id.into(),
       departure: FixedStr::default(),
       arrival: FixedStr::default(),
+      flags: 0,
+      digest: Digest::default(),
+      atc_id: FixedStr::default(),
+      atc_type: FixedStr::default(),
+      atc_flight_number: FixedStr::default(),
+      title: FixedStr::default(),
+      landings: vec![],
+      phase: None,
     })
   }
 
+  /// Turns on the tamper-evident mode, must be set before any entries are written
+  pub fn set_chained(&mut self) {
+    self.flags |= FLAG_CHAINED;
+    self.digest = chain_seed(&self.get_flight_id());
+  }
+
+  pub fn is_chained(&self) -> bool {
+    self.flags & FLAG_CHAINED != 0
+  }
+
+  pub fn is_archived(&self) -> bool {
+    self.flags & FLAG_ARCHIVED != 0
+  }
+
+  /// Chain digest of the last entry if the file is chained
+  pub fn digest(&self) -> Option<Digest> {
+    if self.is_chained() {
+      Some(self.digest)
+    } else {
+      None
+    }
+  }
+
+  pub fn encode(&self) -> Vec<u8> {
+    let mut w = Writer::default();
+    w.put_u64(self.magic);
+    w.put_u64(self.version);
+    w.put_u64(self.updated_at);
+    w.put_u64(self.count);
+    self.flight_id.encode(&mut w);
+    self.departure.encode(&mut w);
+    self.arrival.encode(&mut w);
+    w.put_u32(self.flags);
+    w.put_bytes(&self.digest);
+    self.atc_id.encode(&mut w);
+    self.atc_type.encode(&mut w);
+    self.atc_flight_number.encode(&mut w);
+    self.title.encode(&mut w);
+    w.put_u8(self.landings.len() as u8);
+    for idx in 0..MAX_LANDINGS {
+      match self.landings.get(idx) {
+        Some(landing) => landing.encode(&mut w),
+        None => FixedStr::<8>::default().encode(&mut w),
+      }
+    }
+    w.put_u8(self.phase.map_or(0, |phase| phase as u8 + 1));
+    w.into_inner()
+  }
+
+  pub fn decode(data: &[u8]) -> Result<Self, TrackFileError> {
+    let mut r = Reader::new(data, "header");
+    let mut header = Self {
+      magic: r.u64()?,
+      version: r.u64()?,
+      updated_at: r.u64()?,
+      count: r.u64()?,
+      flight_id: FixedStr::decode(&mut r)?,
+      departure: FixedStr::decode(&mut r)?,
+      arrival: FixedStr::decode(&mut r)?,
+      flags: r.u32()?,
+      digest: r.bytes(32)?.try_into().unwrap(),
+      atc_id: FixedStr::decode(&mut r)?,
+      atc_type: FixedStr::decode(&mut r)?,
+      atc_flight_number: FixedStr::decode(&mut r)?,
+      title: FixedStr::decode(&mut r)?,
+      landings: vec![],
+      phase: None,
+    };
+    let count = r.u8()? as usize;
+    if count > MAX_LANDINGS {
+      return Err(r.invalid("landings count", count as u64));
+    }
+    for idx in 0..MAX_LANDINGS {
+      let landing = FixedStr::decode(&mut r)?;
+      if idx < count {
+        header.landings.push(landing);
+      }
+    }
+    header.phase = match r.u8()? {
+      0 => None,
+      code => Some(
+        FlightPhase::from_code(code as i64 - 1)
+          .ok_or_else(|| r.invalid("flight phase", code as u64))?,
+      ),
+    };
+    Ok(header)
+  }
+
+  /// Reads magic number and version which are located at the same offsets
+  /// in every version of the header
+  pub fn peek_version(data: &[u8]) -> Result<(u64, u64), TrackFileError> {
+    let mut r = Reader::new(data, "header");
+    Ok((r.u64()?, r.u64()?))
+  }
+
   pub fn check_magic(&self) -> bool {
     self.magic == HEADER_MAGIC_NUMBER
   }
@@ -74,8 +221,104 @@ impl Header {
     arr.into()
   }
 
+  /// Appends an intermediate landing, landings beyond `MAX_LANDINGS`
+  /// are not kept
+  pub fn add_landing(&mut self, airport: &str) {
+    if self.landings.len() < MAX_LANDINGS {
+      self.landings.push(airport.into());
+    }
+    self.touch();
+  }
+
+  pub fn get_landings(&self) -> Vec<String> {
+    self.landings.iter().map(|l| l.into()).collect()
+  }
+
+  /// Phase of the flight as of the last phase event
+  pub fn get_phase(&self) -> Option<FlightPhase> {
+    self.phase
+  }
+
   pub fn get_flight_id(&self) -> String {
     let fid = &self.flight_id;
     fid.into()
   }
+
+  /// Sets the ATC and aircraft metadata
+  pub fn set_aircraft(
+    &mut self,
+    atc_id: &str,
+    atc_type: &str,
+    atc_flight_number: &str,
+    title: &str,
+  ) {
+    self.atc_id.set(atc_id);
+    self.atc_type.set(atc_type);
+    self.atc_flight_number.set(atc_flight_number);
+    self.title.set(title);
+    self.touch();
+  }
+
+  pub fn get_atc_id(&self) -> String {
+    (&self.atc_id).into()
+  }
+
+  pub fn get_atc_type(&self) -> String {
+    (&self.atc_type).into()
+  }
+
+  pub fn get_atc_flight_number(&self) -> String {
+    (&self.atc_flight_number).into()
+  }
+
+  pub fn get_title(&self) -> String {
+    (&self.title).into()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_aircraft() -> Result<(), TrackFileError> {
+    let mut header = Header::new("E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    header.set_aircraft("DLH4AB", "A20N", "4AB", "Airbus A320neo Lufthansa");
+    let data = header.encode();
+    assert_eq!(data.len(), HEADER_SIZE);
+
+    let header = Header::decode(&data)?;
+    assert_eq!(header.get_atc_id(), "DLH4AB");
+    assert_eq!(header.get_atc_type(), "A20N");
+    assert_eq!(header.get_atc_flight_number(), "4AB");
+    assert_eq!(header.get_title(), "Airbus A320neo Lufthansa");
+    Ok(())
+  }
+
+  #[test]
+  fn test_landings() -> Result<(), TrackFileError> {
+    let mut header = Header::new("E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    for _ in 0..MAX_LANDINGS {
+      header.add_landing("EGLL");
+    }
+    header.add_landing("EGKK");
+    let data = header.encode();
+    assert_eq!(data.len(), HEADER_SIZE);
+
+    let header = Header::decode(&data)?;
+    assert_eq!(header.get_landings(), vec!["EGLL"; MAX_LANDINGS]);
+    Ok(())
+  }
+
+  #[test]
+  fn test_phase() -> Result<(), TrackFileError> {
+    let mut header = Header::new("E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    assert_eq!(Header::decode(&header.encode())?.get_phase(), None);
+    header.phase = Some(FlightPhase::Preflight);
+    assert_eq!(
+      Header::decode(&header.encode())?.get_phase(),
+      Some(FlightPhase::Preflight)
+    );
+    Ok(())
+  }
 }
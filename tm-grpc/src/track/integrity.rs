@@ -0,0 +1,85 @@
+//! Entry checksums and the optional hash chain.
+//!
+//! Every entry carries a crc32 which catches torn writes and bit rot. In
+//! chained files every entry also carries a link of a SHA-256 chain seeded
+//! with the flight id, and the header holds the digest of the last link.
+//! The chain is not keyed and the digest is stored in the same file, so
+//! anyone able to edit the file can rebuild both. Tampering is only evident
+//! against a digest recorded elsewhere, e.g. the one the server logs when
+//! an upload ends, which has to be kept out of reach of the file editors.
+
+use super::error::TrackFileError;
+use sha2::{Digest as _, Sha256};
+
+pub type Digest = [u8; 32];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyStatus {
+  Ok,
+  BadEntry {
+    index: usize,
+    reason: String,
+  },
+  /// all the entries are intact but the chain doesn't match the header digest
+  DigestMismatch,
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifyReport {
+  pub count: u64,
+  pub status: VerifyStatus,
+  /// final chain digest stored in the header, for chained files only
+  pub digest: Option<Digest>,
+}
+
+/// Number of bytes of the chain link stored with every entry. The full
+/// digest of the last link is kept in the header.
+pub const LINK_SIZE: usize = 8;
+
+/// Size of the trailer appended to each entry: the truncated chain link
+/// followed by a crc32 of the payload and the link
+pub const TRAILER_SIZE: usize = LINK_SIZE + 4;
+
+/// Appends the integrity trailer to an encoded entry payload.
+/// Entries of files which are not chained carry an all-zero link.
+pub fn seal(mut payload: Vec<u8>, link: Option<&Digest>) -> Vec<u8> {
+  match link {
+    Some(link) => payload.extend_from_slice(&link[..LINK_SIZE]),
+    None => payload.extend_from_slice(&[0; LINK_SIZE]),
+  }
+  let crc = crc32fast::hash(&payload);
+  payload.extend_from_slice(&crc.to_le_bytes());
+  payload
+}
+
+/// Checks the crc32 of a sealed entry and splits it into payload and link
+pub fn unseal(data: &[u8]) -> Result<(&[u8], &[u8]), TrackFileError> {
+  if data.len() < TRAILER_SIZE {
+    return Err(TrackFileError::InsufficientDataLength(
+      "sealed track entry".to_owned(),
+      data.len(),
+    ));
+  }
+  let (body, crc) = data.split_at(data.len() - 4);
+  let crc = u32::from_le_bytes(crc.try_into().unwrap());
+  if crc32fast::hash(body) != crc {
+    return Err(TrackFileError::ChecksumMismatch);
+  }
+  Ok(body.split_at(body.len() - LINK_SIZE))
+}
+
+/// Initial chain value, binding the chain to the flight
+pub fn chain_seed(flight_id: &str) -> Digest {
+  Sha256::digest(flight_id.as_bytes()).into()
+}
+
+pub fn chain_next(prev: &Digest, payload: &[u8]) -> Digest {
+  let mut hasher = Sha256::new();
+  hasher.update(prev);
+  hasher.update(payload);
+  hasher.finalize().into()
+}
+
+pub fn to_hex(digest: &Digest) -> String {
+  digest.iter().map(|b| format!("{b:02x}")).collect()
+}
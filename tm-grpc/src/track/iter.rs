@@ -0,0 +1,157 @@
+use super::{entry::TrackFileEntry, error::TrackFileError, format::Format};
+use std::ops::Range;
+
+/// Number of entries read from disk at once
+pub const CHUNK_LEN: usize = 1024;
+
+/// Track file giving access to its encoded entries, shared by the
+/// readers of `TrackFile` and `MappedTrackFile`
+pub trait RawEntries {
+  fn format(&self) -> Format;
+
+  /// Reads `len` encoded entries starting at `pos`, decompressing them
+  /// if the file is archived. The range must be within the file.
+  fn read_raw(&self, pos: usize, len: usize) -> Result<Vec<u8>, TrackFileError>;
+}
+
+/// Returns the index of the first of the `count` entries with a timestamp
+/// at or after `ts`, or `count` if there's no such entry. Entries are
+/// expected to be ordered by timestamp, which the server keeps for all
+/// but the touchdowns reported by clients: those may follow a few points
+/// of the landing roll, and a search for a time within those seconds
+/// may land a few entries off.
+pub fn find_ts<T: RawEntries>(tf: &T, count: usize, ts: u64) -> Result<usize, TrackFileError> {
+  let mut lo = 0;
+  let mut hi = count;
+  while lo < hi {
+    let mid = lo + (hi - lo) / 2;
+    let entry = tf.format().decode_entry(&tf.read_raw(mid, 1)?)?;
+    if entry.ts() < ts {
+      lo = mid + 1;
+    } else {
+      hi = mid;
+    }
+  }
+  Ok(lo)
+}
+
+/// Returns the range of the entries with timestamps within
+/// `start_at..end_at`, unbounded on the right if `end_at` is not set
+pub fn time_range<T: RawEntries>(
+  tf: &T,
+  count: usize,
+  start_at: u64,
+  end_at: Option<u64>,
+) -> Result<Range<usize>, TrackFileError> {
+  let start = find_ts(tf, count, start_at)?;
+  let end = match end_at {
+    Some(end_at) => find_ts(tf, count, end_at)?,
+    None => count,
+  };
+  Ok(start..end.max(start))
+}
+
+/// Lazy iterator over a range of track file entries. Entries are read
+/// in chunks of `CHUNK_LEN` and decoded one by one, so only a single chunk
+/// is kept in memory. The range is fixed when the iterator is created,
+/// entries appended afterwards are not yielded.
+pub struct Entries<'a, T> {
+  tf: &'a T,
+  pos: usize,
+  end: usize,
+  chunk: Vec<u8>,
+  offset: usize,
+}
+
+impl<'a, T: RawEntries> Entries<'a, T> {
+  /// Iterates over the entries within the range of indices,
+  /// the range is clamped to the `count` entries of the file
+  pub(super) fn new(tf: &'a T, range: Range<usize>, count: usize) -> Self {
+    let end = range.end.min(count);
+    Self {
+      tf,
+      pos: range.start.min(end),
+      end,
+      chunk: Vec::new(),
+      offset: 0,
+    }
+  }
+
+  /// Index of the entry to be yielded next
+  pub fn position(&self) -> usize {
+    self.pos
+  }
+}
+
+impl<T: RawEntries> Iterator for Entries<'_, T> {
+  type Item = Result<TrackFileEntry, TrackFileError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.pos >= self.end {
+      return None;
+    }
+
+    if self.offset >= self.chunk.len() {
+      let len = CHUNK_LEN.min(self.end - self.pos);
+      match self.tf.read_raw(self.pos, len) {
+        Ok(chunk) => {
+          self.chunk = chunk;
+          self.offset = 0;
+        }
+        Err(err) => {
+          // stop iterating after an i/o error
+          self.pos = self.end;
+          return Some(Err(err));
+        }
+      }
+    }
+
+    let entry_size = self.tf.format().entry_size();
+    let data = &self.chunk[self.offset..self.offset + entry_size];
+    self.offset += entry_size;
+    self.pos += 1;
+    Some(self.tf.format().decode_entry(data))
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let len = self.end - self.pos;
+    (len, Some(len))
+  }
+}
+
+impl<T: RawEntries> ExactSizeIterator for Entries<'_, T> {}
+
+#[cfg(test)]
+mod tests {
+  use crate::track::{
+    entry::{TrackFileEntry, TrackPoint},
+    trackfile::TrackFile,
+  };
+  use tempfile::NamedTempFile;
+
+  #[test]
+  fn test_entries_range() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let mut tf = TrackFile::create(temp.path(), "E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    for ts in 0..2100 {
+      let tp = TrackPoint {
+        ts,
+        lat: ts as f64 / 1000.0,
+        ..Default::default()
+      };
+      tf.append(&TrackFileEntry::TrackPoint(tp))?;
+    }
+
+    let entries = tf.entries_range(1000..2050)?;
+    assert_eq!(entries.len(), 1050);
+    let ts = entries
+      .map(|e| e.map(|e| e.ts()))
+      .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(ts, (1000..2050).collect::<Vec<_>>());
+
+    assert_eq!(tf.entries_range(2000..3000)?.len(), 100);
+    assert_eq!(tf.entries_range(3000..4000)?.len(), 0);
+    assert_eq!(tf.entries()?.count(), 2100);
+    Ok(())
+  }
+}
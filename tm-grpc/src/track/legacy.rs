@@ -0,0 +1,190 @@
+//! Version 1 track file layout.
+//!
+//! v1 files were produced by copying the in-memory representation of `Header`
+//! and `TrackFileEntry` as is, so the layout below mirrors what rustc generated
+//! for those structs on x86_64 (little-endian, 8-byte aligned). The offsets are
+//! spelled out explicitly here so that v1 files can be read and migrated
+//! without relying on the current struct layout.
+
+use super::{
+  codec::{Reader, Writer},
+  entry::{TouchDown, TrackFileEntry, TrackPoint},
+  error::TrackFileError,
+  fixedstr::FixedStr,
+  header::Header,
+  integrity::Digest,
+};
+
+pub const HEADER_VERSION: u64 = 1;
+pub const HEADER_SIZE: usize = 112;
+pub const ENTRY_SIZE: usize = 152;
+
+const TAG_TRACK_POINT: u32 = 0;
+const TAG_TOUCH_DOWN: u32 = 1;
+const PAYLOAD_OFFSET: usize = 8;
+
+fn decode_str<const N: usize>(r: &mut Reader) -> Result<FixedStr<N>, TrackFileError> {
+  let len = r.u64()?;
+  let data = r.bytes(N)?;
+  FixedStr::from_raw_parts(len as usize, data).ok_or_else(|| r.invalid("string length", len))
+}
+
+fn encode_str<const N: usize>(w: &mut Writer, s: &FixedStr<N>) {
+  w.put_u64(s.raw_len() as u64);
+  w.put_bytes(s.raw_data());
+}
+
+pub fn decode_header(data: &[u8]) -> Result<Header, TrackFileError> {
+  let mut r = Reader::new(data, "v1 header");
+  let magic = r.u64()?;
+  let version = r.u64()?;
+  let updated_at = r.u64()?;
+  let count = r.u64()?;
+  let flight_id = decode_str(&mut r)?;
+  // 4 bytes of padding after flight_id data
+  r.skip(4)?;
+  let departure = decode_str(&mut r)?;
+  let arrival = decode_str(&mut r)?;
+  Ok(Header {
+    magic,
+    version,
+    updated_at,
+    count,
+    flight_id,
+    departure,
+    arrival,
+    flags: 0,
+    digest: Digest::default(),
+    atc_id: FixedStr::default(),
+    atc_type: FixedStr::default(),
+    atc_flight_number: FixedStr::default(),
+    title: FixedStr::default(),
+    landings: vec![],
+    phase: None,
+  })
+}
+
+pub fn encode_header(header: &Header) -> Vec<u8> {
+  let mut w = Writer::default();
+  w.put_u64(header.magic);
+  w.put_u64(header.version);
+  w.put_u64(header.updated_at);
+  w.put_u64(header.count);
+  encode_str(&mut w, &header.flight_id);
+  w.pad_to(80);
+  encode_str(&mut w, &header.departure);
+  encode_str(&mut w, &header.arrival);
+  w.into_inner()
+}
+
+pub fn decode_entry(data: &[u8]) -> Result<TrackFileEntry, TrackFileError> {
+  let mut r = Reader::new(data, "v1 track entry");
+  let tag = r.u32()?;
+  r.seek(PAYLOAD_OFFSET);
+  let entry = match tag {
+    TAG_TRACK_POINT => {
+      let ts = r.u64()?;
+      let lat = r.f64()?;
+      let lng = r.f64()?;
+      let hdg_true = r.f64()?;
+      let alt_amsl = r.f64()?;
+      let alt_agl = r.f64()?;
+      let gnd_height = r.f64()?;
+      let crs = r.f64()?;
+      let ias = r.f64()?;
+      let tas = r.f64()?;
+      let gs = r.f64()?;
+      let ap_master = r.bool()?;
+      r.skip(7)?;
+      let gear_pct = r.i64()?;
+      let flaps = r.i64()?;
+      let on_gnd = r.bool()?;
+      let on_rwy = r.bool()?;
+      r.skip(6)?;
+      let wind_vel = r.f64()?;
+      let wind_dir = r.f64()?;
+      let distance = r.f64()?;
+      TrackFileEntry::TrackPoint(TrackPoint {
+        ts,
+        lat,
+        lng,
+        hdg_true,
+        alt_amsl,
+        alt_agl,
+        gnd_height,
+        crs,
+        ias,
+        tas,
+        gs,
+        ap_master,
+        gear_pct,
+        flaps,
+        on_gnd,
+        on_rwy,
+        wind_vel,
+        wind_dir,
+        distance,
+        ..Default::default()
+      })
+    }
+    TAG_TOUCH_DOWN => TrackFileEntry::TouchDown(TouchDown {
+      ts: r.u64()?,
+      bank: r.f64()?,
+      hdg_mag: r.f64()?,
+      hdg_true: r.f64()?,
+      vel_nrm: r.f64()?,
+      pitch: r.f64()?,
+      lat: r.f64()?,
+      lng: r.f64()?,
+      ..Default::default()
+    }),
+    tag => return Err(r.invalid("entry tag", tag as u64)),
+  };
+  Ok(entry)
+}
+
+pub fn encode_entry(entry: &TrackFileEntry) -> Vec<u8> {
+  let mut w = Writer::default();
+  match entry {
+    TrackFileEntry::TrackPoint(tp) => {
+      w.put_u32(TAG_TRACK_POINT);
+      w.pad_to(PAYLOAD_OFFSET);
+      w.put_u64(tp.ts);
+      w.put_f64(tp.lat);
+      w.put_f64(tp.lng);
+      w.put_f64(tp.hdg_true);
+      w.put_f64(tp.alt_amsl);
+      w.put_f64(tp.alt_agl);
+      w.put_f64(tp.gnd_height);
+      w.put_f64(tp.crs);
+      w.put_f64(tp.ias);
+      w.put_f64(tp.tas);
+      w.put_f64(tp.gs);
+      w.put_bool(tp.ap_master);
+      w.pad_to(PAYLOAD_OFFSET + 96);
+      w.put_i64(tp.gear_pct);
+      w.put_i64(tp.flaps);
+      w.put_bool(tp.on_gnd);
+      w.put_bool(tp.on_rwy);
+      w.pad_to(PAYLOAD_OFFSET + 120);
+      w.put_f64(tp.wind_vel);
+      w.put_f64(tp.wind_dir);
+      w.put_f64(tp.distance);
+    }
+    TrackFileEntry::TouchDown(td) => {
+      w.put_u32(TAG_TOUCH_DOWN);
+      w.pad_to(PAYLOAD_OFFSET);
+      w.put_u64(td.ts);
+      w.put_f64(td.bank);
+      w.put_f64(td.hdg_mag);
+      w.put_f64(td.hdg_true);
+      w.put_f64(td.vel_nrm);
+      w.put_f64(td.pitch);
+      w.put_f64(td.lat);
+      w.put_f64(td.lng);
+    }
+    TrackFileEntry::Event(_) => unreachable!("v1 files are migrated before appending"),
+  }
+  w.pad_to(ENTRY_SIZE);
+  w.into_inner()
+}
@@ -0,0 +1,281 @@
+use super::{
+  archive::ArchiveReader,
+  entry::TrackFileEntry,
+  error::TrackFileError,
+  format::{Format, MAX_HEADER_SIZE},
+  header::Header,
+  iter::{find_ts, time_range, Entries, RawEntries},
+};
+use memmap2::Mmap;
+use std::{
+  fs::File,
+  ops::Range,
+  os::unix::prelude::FileExt,
+  path::{Path, PathBuf},
+};
+
+/// Read-only view of a track file backed by a memory map.
+///
+/// The view covers the file as it was when it was mapped. A file which is
+/// being appended to can be picked up with `refresh`. Entries are validated
+/// on decoding like with `TrackFile`. Appended entries are never modified
+/// and files are never truncated in place, repair, archiving and migration
+/// replace them with new ones leaving the mapped file intact. The header is
+/// rewritten in place by every append, so it's read from the file rather
+/// than through the map.
+pub struct MappedTrackFile {
+  file: File,
+  path: PathBuf,
+  format: Format,
+  map: Mmap,
+  header: Header,
+  count: usize,
+  archive: Option<ArchiveReader>,
+}
+
+impl MappedTrackFile {
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, TrackFileError> {
+    let path = path.as_ref().to_path_buf();
+    let file = File::open(&path).map_err(|err| match err.kind() {
+      std::io::ErrorKind::NotFound => TrackFileError::NotFound(path.to_string_lossy().to_string()),
+      _ => err.into(),
+    })?;
+
+    let mut buf = vec![0; MAX_HEADER_SIZE];
+    let len = file.read_at(&mut buf, 0)?;
+    let format = Format::detect(&buf[..len])?;
+    let header = format.decode_header(&buf[..len])?;
+    // mapped after the header is read, so that it covers all the entries
+    // the header claims as they are written before it
+    let map = map_file(&file)?;
+    let archive = if header.is_archived() {
+      let count = header.count() as usize;
+      Some(ArchiveReader::open(&file, format.header_size(), count)?)
+    } else {
+      None
+    };
+
+    let mut tf = Self {
+      file,
+      path,
+      format,
+      map,
+      header,
+      count: 0,
+      archive,
+    };
+    tf.count = tf.check()?;
+    Ok(tf)
+  }
+
+  /// Returns the number of entries claimed by the header
+  /// if the length of the file matches them
+  fn check(&self) -> Result<usize, TrackFileError> {
+    let count = self.header.count() as usize;
+    let len = self.map.len();
+    let expected_len = match &self.archive {
+      Some(archive) => archive.file_len() as usize,
+      None => self.format.header_size() + count * self.format.entry_size(),
+    };
+    // the entry past the last one may be in the middle of being appended,
+    // anything beyond that is garbage left by a crash and has to be repaired
+    let slack = match &self.archive {
+      Some(_) => 0,
+      None => self.format.entry_size(),
+    };
+    if len < expected_len || len > expected_len + slack {
+      return Err(TrackFileError::InvalidFileLength(expected_len, len));
+    }
+    Ok(count)
+  }
+
+  /// Remaps the file if it has grown since it was mapped.
+  /// Returns true if new entries have become available.
+  pub fn refresh(&mut self) -> Result<bool, TrackFileError> {
+    if self.archive.is_some() {
+      // archived files are never modified in place
+      return Ok(false);
+    }
+
+    let mut buf = vec![0; self.format.header_size()];
+    self.file.read_exact_at(&mut buf, 0)?;
+    let header = self.format.decode_header(&buf)?;
+    let len = self.file.metadata()?.len() as usize;
+    if len > self.map.len() {
+      self.map = map_file(&self.file)?;
+    }
+
+    // the header is written after the entries so the file holds all the
+    // entries it claims, the mapping is made after the header is read
+    let mapped = (self.map.len() - self.format.header_size()) / self.format.entry_size();
+    let count = (header.count() as usize).min(mapped);
+    self.header = header;
+
+    let grown = count > self.count;
+    self.count = count;
+    Ok(grown)
+  }
+
+  pub fn format(&self) -> Format {
+    self.format
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub fn header(&self) -> &Header {
+    &self.header
+  }
+
+  pub fn count(&self) -> usize {
+    self.count
+  }
+
+  pub fn is_archived(&self) -> bool {
+    self.archive.is_some()
+  }
+
+  pub fn read_at(&self, pos: usize) -> Result<TrackFileEntry, TrackFileError> {
+    if pos >= self.count {
+      return Err(TrackFileError::IndexError(pos));
+    }
+    let entry_size = self.format.entry_size();
+    match &self.archive {
+      Some(archive) => {
+        let data = archive.read_raw(&self.file, pos, 1, entry_size)?;
+        self.format.decode_entry(&data)
+      }
+      None => {
+        let offset = self.format.header_size() + pos * entry_size;
+        self
+          .format
+          .decode_entry(&self.map[offset..offset + entry_size])
+      }
+    }
+  }
+
+  /// Iterates over the entries within the range of indices,
+  /// the range is clamped to the number of entries
+  pub fn entries_range(&self, range: Range<usize>) -> Entries<'_, Self> {
+    Entries::new(self, range, self.count)
+  }
+
+  pub fn entries(&self) -> Entries<'_, Self> {
+    self.entries_range(0..self.count)
+  }
+
+  /// Returns the index of the first entry with a timestamp at or after `ts`,
+  /// see `TrackFile::find_ts`
+  pub fn find_ts(&self, ts: u64) -> Result<usize, TrackFileError> {
+    find_ts(self, self.count, ts)
+  }
+
+  /// Iterates over the entries with timestamps within `start_at..end_at`,
+  /// unbounded on the right if `end_at` is not set
+  pub fn entries_time_range(
+    &self,
+    start_at: u64,
+    end_at: Option<u64>,
+  ) -> Result<Entries<'_, Self>, TrackFileError> {
+    let range = time_range(self, self.count, start_at, end_at)?;
+    Ok(Entries::new(self, range, self.count))
+  }
+}
+
+impl RawEntries for MappedTrackFile {
+  fn format(&self) -> Format {
+    self.format
+  }
+
+  fn read_raw(&self, pos: usize, len: usize) -> Result<Vec<u8>, TrackFileError> {
+    let entry_size = self.format.entry_size();
+    match &self.archive {
+      Some(archive) => archive.read_raw(&self.file, pos, len, entry_size),
+      None => {
+        let offset = self.format.header_size() + pos * entry_size;
+        Ok(self.map[offset..offset + len * entry_size].to_vec())
+      }
+    }
+  }
+}
+
+fn map_file(file: &File) -> Result<Mmap, TrackFileError> {
+  // SAFETY: the mapped part of a track file is never truncated or written
+  // to except for the header, which isn't read through the map, see the
+  // struct docs
+  let map = unsafe { Mmap::map(file)? };
+  Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::track::{
+    entry::{TrackFileEntry, TrackPoint},
+    trackfile::TrackFile,
+  };
+  use tempfile::NamedTempFile;
+
+  #[test]
+  fn test_mapped() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let mut tf = TrackFile::create(temp.path(), "E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    for ts in 0..10 {
+      let tp = TrackPoint {
+        ts: ts * 1000,
+        lat: ts as f64,
+        ..Default::default()
+      };
+      tf.append(&TrackFileEntry::TrackPoint(tp))?;
+    }
+
+    let mut mtf = MappedTrackFile::open(temp.path())?;
+    assert_eq!(mtf.count(), 10);
+    assert_eq!(mtf.header().get_flight_id(), tf.flight_id());
+    assert_eq!(mtf.read_at(3)?, tf.read_at(3)?);
+    assert_eq!(mtf.find_ts(4500)?, 5);
+    assert_eq!(mtf.entries_time_range(2000, Some(4000))?.count(), 2);
+    assert!(matches!(
+      mtf.read_at(10),
+      Err(TrackFileError::IndexError(10))
+    ));
+    assert!(!mtf.refresh()?);
+
+    let tp = TrackPoint {
+      ts: 10000,
+      lat: 10.0,
+      ..Default::default()
+    };
+    tf.append(&TrackFileEntry::TrackPoint(tp))?;
+    assert!(mtf.refresh()?);
+    assert_eq!(mtf.count(), 11);
+    assert_eq!(mtf.read_at(10)?, tf.read_at(10)?);
+
+    let entries = mtf.entries().collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(entries, tf.read_all()?);
+    Ok(())
+  }
+
+  #[test]
+  fn test_trailing_data() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let mut tf = TrackFile::create(temp.path(), "E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    tf.append(&TrackFileEntry::TrackPoint(Default::default()))?;
+    let entry_size = tf.format().entry_size();
+    let file = std::fs::OpenOptions::new().write(true).open(temp.path())?;
+    let len = file.metadata()?.len();
+
+    // an entry being appended is not visible yet
+    file.write_all_at(&vec![0; entry_size], len)?;
+    assert_eq!(MappedTrackFile::open(temp.path())?.count(), 1);
+
+    // anything beyond that needs a repair
+    file.write_all_at(&[0], len + entry_size as u64)?;
+    assert!(matches!(
+      MappedTrackFile::open(temp.path()),
+      Err(TrackFileError::InvalidFileLength(_, _))
+    ));
+    Ok(())
+  }
+}
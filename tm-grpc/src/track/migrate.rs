@@ -0,0 +1,165 @@
+use super::{error::TrackFileError, format::Format, header::FLAG_ARCHIVED, trackfile::TrackFile};
+use std::{
+  fmt::Display,
+  fs::{self, File},
+  io::{BufWriter, ErrorKind, Write},
+  path::{Path, PathBuf},
+};
+use tempfile::Builder;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationStatus {
+  Migrated { from: Format, to: Format },
+  UpToDate,
+}
+
+impl Display for MigrationStatus {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      MigrationStatus::Migrated { from, to } => {
+        write!(f, "migrated v{} -> v{}", from.version(), to.version())
+      }
+      MigrationStatus::UpToDate => write!(f, "up to date"),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct MigrationResult {
+  pub path: PathBuf,
+  pub status: Result<MigrationStatus, TrackFileError>,
+}
+
+/// Rewrites a track file in the target format.
+///
+/// The new content is written to a temporary file in the same folder which
+/// is synced and then renamed over the original, so a crash at any point
+/// leaves either the old or the new file in place. The file must not be
+/// written to while it's being migrated. Chained files are never rewritten
+/// as that would change their digest.
+pub fn migrate_file<P: AsRef<Path>>(
+  path: P,
+  target: Format,
+) -> Result<MigrationStatus, TrackFileError> {
+  let path = path.as_ref();
+  let tf = TrackFile::open(path)?;
+  let source = tf.format();
+  if source >= target {
+    return Ok(MigrationStatus::UpToDate);
+  }
+
+  let mut header = tf.get_header()?;
+  if header.is_chained() {
+    // the encoded payloads change between versions, a rebuilt chain
+    // wouldn't match the digest recorded for the flight any more
+    return Err(TrackFileError::Chained);
+  }
+  let entries = tf.read_all()?;
+  header.version = target.version();
+  header.count = entries.len() as u64;
+  // entries are always written in the plain layout
+  header.flags &= !FLAG_ARCHIVED;
+  let records: Vec<Vec<u8>> = entries
+    .iter()
+    .map(|entry| target.encode_entry(entry))
+    .collect();
+
+  replace_file(path, |w| {
+    w.write_all(&target.encode_header(&header))?;
+    for record in records.iter() {
+      w.write_all(record)?;
+    }
+    Ok(())
+  })?;
+
+  Ok(MigrationStatus::Migrated {
+    from: source,
+    to: target,
+  })
+}
+
+/// Atomically replaces the file with the content produced by `write`.
+/// The data goes to a temporary file in the same folder which is synced
+/// and renamed over the original one, keeping its permissions.
+pub fn replace_file<F>(path: &Path, write: F) -> Result<(), TrackFileError>
+where
+  F: FnOnce(&mut BufWriter<&mut File>) -> Result<(), TrackFileError>,
+{
+  let dir = path.parent().unwrap_or(Path::new("."));
+  let mut tmp = Builder::new().prefix(".replace-").tempfile_in(dir)?;
+  {
+    let mut w = BufWriter::new(tmp.as_file_mut());
+    write(&mut w)?;
+    w.flush()?;
+  }
+  // temporary files are created private
+  match fs::metadata(path) {
+    Ok(meta) => tmp.as_file().set_permissions(meta.permissions())?,
+    Err(err) if err.kind() == ErrorKind::NotFound => {}
+    Err(err) => return Err(err.into()),
+  }
+  tmp.as_file().sync_all()?;
+  tmp.persist(path).map_err(|err| err.error)?;
+  File::open(dir)?.sync_all()?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::track::{
+    entry::{TrackFileEntry, TrackPoint},
+    trackfile::tests::write_v1_file,
+  };
+  use tempfile::NamedTempFile;
+
+  #[test]
+  fn test_migrate_v1() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let entries: Vec<TrackFileEntry> = (0..3)
+      .map(|i| {
+        TrackFileEntry::TrackPoint(TrackPoint {
+          ts: i * 1000,
+          lat: 51.0 + i as f64,
+          distance: i as f64,
+          ..Default::default()
+        })
+      })
+      .collect();
+    write_v1_file(temp.path(), &entries)?;
+
+    let status = migrate_file(temp.path(), Format::LATEST)?;
+    assert_eq!(
+      status,
+      MigrationStatus::Migrated {
+        from: Format::V1,
+        to: Format::LATEST
+      }
+    );
+
+    let tf = TrackFile::open(temp.path())?;
+    assert_eq!(tf.format(), Format::LATEST);
+    assert_eq!(tf.get_departure()?, "EGLL");
+    assert_eq!(tf.read_all()?, entries);
+
+    let status = migrate_file(temp.path(), Format::LATEST)?;
+    assert_eq!(status, MigrationStatus::UpToDate);
+    Ok(())
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_replace_keeps_permissions() -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = NamedTempFile::new()?;
+    fs::set_permissions(temp.path(), fs::Permissions::from_mode(0o644))?;
+    replace_file(temp.path(), |w| Ok(w.write_all(b"replaced")?))?;
+    assert_eq!(fs::read(temp.path())?, b"replaced");
+    assert_eq!(
+      fs::metadata(temp.path())?.permissions().mode() & 0o777,
+      0o644
+    );
+    Ok(())
+  }
+}
@@ -1,6 +1,15 @@
+pub mod archive;
+pub mod codec;
 pub mod entry;
 pub mod error;
 pub mod fixedstr;
+pub mod format;
 pub mod header;
+pub mod integrity;
+pub mod iter;
+pub mod legacy;
+pub mod mapped;
+pub mod migrate;
+pub mod repair;
 pub mod store;
 pub mod trackfile;
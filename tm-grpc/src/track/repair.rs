@@ -0,0 +1,339 @@
+use super::{
+  error::TrackFileError,
+  format::Format,
+  format::MAX_HEADER_SIZE,
+  header::Header,
+  integrity::{chain_next, chain_seed, unseal, Digest, LINK_SIZE, TRAILER_SIZE},
+  iter::CHUNK_LEN,
+  migrate::replace_file,
+  trackfile::TrackFile,
+};
+use chrono::Utc;
+use std::{
+  fmt::Display,
+  fs::{self, File},
+  io::Write,
+  os::unix::prelude::FileExt,
+  path::{Path, PathBuf},
+};
+
+#[derive(Debug, Default, Clone)]
+pub struct RepairOptions {
+  /// Folder to move the files which can't be recovered to.
+  /// If not set, such files are left intact and an error is returned.
+  pub quarantine: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepairStatus {
+  Intact,
+  Repaired {
+    header_count: u64,
+    count: u64,
+    truncated: u64,
+  },
+  Quarantined(PathBuf),
+}
+
+impl Display for RepairStatus {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      RepairStatus::Intact => write!(f, "track file is intact"),
+      RepairStatus::Repaired {
+        header_count,
+        count,
+        truncated,
+      } => write!(
+        f,
+        "track file repaired: header count {header_count} -> {count}, {truncated} bytes truncated"
+      ),
+      RepairStatus::Quarantined(path) => {
+        write!(
+          f,
+          "track file is unrecoverable, moved to {}",
+          path.display()
+        )
+      }
+    }
+  }
+}
+
+/// Brings a track file into a consistent state after a crash.
+///
+/// A partially written trailing entry is dropped, as well as trailing
+/// entries which fail to decode, and the header count is set to the number
+/// of entries actually present in the file. The chain digest of a chained
+/// file is brought up to the kept entries, a trailing entry which doesn't
+/// follow the chain is dropped. Files with an unreadable header
+/// are moved to the quarantine folder if one is configured, as well as
+/// archived files which don't pass validation. Repaired files are replaced
+/// with a new copy, never truncated in place.
+pub fn repair_file<P: AsRef<Path>>(
+  path: P,
+  opts: &RepairOptions,
+) -> Result<RepairStatus, TrackFileError> {
+  let path = path.as_ref();
+  let file = File::open(path)?;
+
+  let mut buf = vec![0; MAX_HEADER_SIZE];
+  let len = file.read_at(&mut buf, 0)?;
+  let res = Format::detect(&buf[..len]).and_then(|format| {
+    let header = format.decode_header(&buf[..len])?;
+    Ok((format, header))
+  });
+
+  let (format, mut header): (Format, Header) = match res {
+    Ok(res) => res,
+    Err(err) => {
+      drop(file);
+      return match &opts.quarantine {
+        Some(folder) => quarantine(path, folder).map(RepairStatus::Quarantined),
+        None => Err(err),
+      };
+    }
+  };
+
+  if header.is_archived() {
+    // archived files are written atomically and never appended to,
+    // so there is nothing to truncate, the file is either valid or not
+    drop(file);
+    return match TrackFile::open(path) {
+      Ok(_) => Ok(RepairStatus::Intact),
+      Err(err) => match &opts.quarantine {
+        Some(folder) => quarantine(path, folder).map(RepairStatus::Quarantined),
+        None => Err(err),
+      },
+    };
+  }
+
+  let header_size = format.header_size() as u64;
+  let entry_size = format.entry_size() as u64;
+  let file_len = file.metadata()?.len();
+  let mut count = file_len.saturating_sub(header_size) / entry_size;
+
+  let mut entry_buf = vec![0; entry_size as usize];
+  while count > 0 {
+    file.read_exact_at(&mut entry_buf, header_size + (count - 1) * entry_size)?;
+    if format.decode_entry(&entry_buf).is_ok() {
+      break;
+    }
+    count -= 1;
+  }
+
+  // entries written without their header update extend the chain
+  // from the header digest, the ones the header claims but the file
+  // lacks have to be rolled back by replaying the chain from the start
+  if header.is_chained() {
+    if count > header.count {
+      let mut digest = header.digest;
+      let mut pos = header.count;
+      while pos < count {
+        file.read_exact_at(&mut entry_buf, header_size + pos * entry_size)?;
+        let (payload, link) = unseal(&entry_buf)?;
+        let next = chain_next(&digest, payload);
+        if next[..LINK_SIZE] != *link {
+          break;
+        }
+        digest = next;
+        pos += 1;
+      }
+      count = pos;
+      header.digest = digest;
+    } else if count < header.count {
+      header.digest = replay_chain(&file, format, count, &header.get_flight_id())?;
+    }
+  }
+
+  let expected_len = header_size + count * entry_size;
+  if header.count == count && file_len == expected_len {
+    return Ok(RepairStatus::Intact);
+  }
+
+  let header_count = header.count;
+  header.count = count;
+  header.touch();
+  // the file is replaced rather than truncated in place as the mapped
+  // views of downloads in progress would fault past the new end
+  replace_file(path, |w| {
+    w.write_all(&format.encode_header(&header))?;
+    let mut buf = vec![0; CHUNK_LEN * entry_size as usize];
+    let mut pos = 0;
+    while pos < count {
+      let len = (count - pos).min(CHUNK_LEN as u64);
+      let chunk = &mut buf[..(len * entry_size) as usize];
+      file.read_exact_at(chunk, header_size + pos * entry_size)?;
+      w.write_all(chunk)?;
+      pos += len;
+    }
+    Ok(())
+  })?;
+
+  Ok(RepairStatus::Repaired {
+    header_count,
+    count,
+    truncated: file_len - expected_len,
+  })
+}
+
+/// Computes the chain digest of the first `count` entries. The checksums
+/// and the links stored with the entries are left to `verify` to check.
+fn replay_chain(
+  file: &File,
+  format: Format,
+  count: u64,
+  flight_id: &str,
+) -> Result<Digest, TrackFileError> {
+  let header_size = format.header_size() as u64;
+  let entry_size = format.entry_size();
+  let mut digest = chain_seed(flight_id);
+  let mut buf = vec![0; CHUNK_LEN * entry_size];
+  let mut pos = 0;
+  while pos < count {
+    let len = (count - pos).min(CHUNK_LEN as u64);
+    let chunk = &mut buf[..len as usize * entry_size];
+    file.read_exact_at(chunk, header_size + pos * entry_size as u64)?;
+    for data in chunk.chunks_exact(entry_size) {
+      digest = chain_next(&digest, &data[..entry_size - TRAILER_SIZE]);
+    }
+    pos += len;
+  }
+  Ok(digest)
+}
+
+fn quarantine(path: &Path, folder: &Path) -> Result<PathBuf, TrackFileError> {
+  fs::create_dir_all(folder)?;
+  let filename = path.file_name().unwrap_or_default().to_string_lossy();
+  let mut target = folder.join(filename.as_ref());
+  if target.exists() {
+    let ts = Utc::now().timestamp_millis();
+    target = folder.join(format!("{filename}.{ts}"));
+  }
+  fs::rename(path, &target)?;
+  Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::track::{
+    entry::{TrackFileEntry, TrackPoint},
+    integrity::VerifyStatus,
+    mapped::MappedTrackFile,
+    trackfile::TrackFile,
+  };
+  use std::fs::OpenOptions;
+  use tempfile::{tempdir, NamedTempFile};
+
+  #[test]
+  fn test_repair_partial_entry() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let mut tf = TrackFile::create(temp.path(), "E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    for ts in 0..3 {
+      let tp = TrackPoint {
+        ts,
+        lat: ts as f64,
+        ..Default::default()
+      };
+      tf.append(&TrackFileEntry::TrackPoint(tp))?;
+    }
+
+    // a download following the flight has the file mapped
+    let mut viewer = MappedTrackFile::open(temp.path())?;
+
+    // simulate a crash after the header count has been incremented
+    // but before the entry has been completely written
+    let format = tf.format();
+    let mut header = tf.get_header()?;
+    header.count += 1;
+    let file = OpenOptions::new().write(true).open(temp.path())?;
+    file.write_all_at(&format.encode_header(&header), 0)?;
+    file.write_all_at(&[1, 2, 3], file.metadata()?.len())?;
+    drop(tf);
+
+    assert!(matches!(
+      TrackFile::open(temp.path()),
+      Err(TrackFileError::InvalidFileLength(_, _))
+    ));
+
+    let status = repair_file(temp.path(), &RepairOptions::default())?;
+    assert_eq!(
+      status,
+      RepairStatus::Repaired {
+        header_count: 4,
+        count: 3,
+        truncated: 3
+      }
+    );
+
+    let tf = TrackFile::open(temp.path())?;
+    assert_eq!(tf.count()?, 3);
+    // the mapped view keeps reading the file it was opened with
+    viewer.refresh()?;
+    assert_eq!(viewer.entries().count(), 3);
+    let status = repair_file(temp.path(), &RepairOptions::default())?;
+    assert_eq!(status, RepairStatus::Intact);
+    Ok(())
+  }
+
+  #[test]
+  fn test_repair_chained() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let mut header = Header::new("E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    header.set_chained();
+    let mut tf = TrackFile::create_with_header(temp.path(), header)?;
+    for ts in 0..5 {
+      tf.append(&TrackFileEntry::TrackPoint(TrackPoint::at(ts)))?;
+    }
+    let format = tf.format();
+    let file = OpenOptions::new().write(true).open(temp.path())?;
+
+    // simulate a crash after the entry has been written
+    // but before the header has been updated
+    let header = tf.get_header()?;
+    tf.append(&TrackFileEntry::TrackPoint(TrackPoint::at(5)))?;
+    file.write_all_at(&format.encode_header(&header), 0)?;
+    drop(tf);
+
+    let status = repair_file(temp.path(), &RepairOptions::default())?;
+    assert!(matches!(status, RepairStatus::Repaired { count: 6, .. }));
+    let mut tf = TrackFile::open(temp.path())?;
+    assert_eq!(tf.verify()?.status, VerifyStatus::Ok);
+    tf.append(&TrackFileEntry::TrackPoint(TrackPoint::at(6)))?;
+    assert_eq!(tf.verify()?.status, VerifyStatus::Ok);
+
+    // and after the header has been written but the entry hasn't,
+    // the file has been replaced by repair
+    tf.append(&TrackFileEntry::TrackPoint(TrackPoint::at(7)))?;
+    let file = OpenOptions::new().write(true).open(temp.path())?;
+    let len = format.header_size() + 7 * format.entry_size();
+    file.set_len(len as u64 + 3)?;
+    drop(tf);
+
+    let status = repair_file(temp.path(), &RepairOptions::default())?;
+    assert!(matches!(status, RepairStatus::Repaired { count: 7, .. }));
+    let mut tf = TrackFile::open(temp.path())?;
+    assert_eq!(tf.verify()?.status, VerifyStatus::Ok);
+    tf.append(&TrackFileEntry::TrackPoint(TrackPoint::at(8)))?;
+    let report = tf.verify()?;
+    assert_eq!((report.count, report.status), (8, VerifyStatus::Ok));
+    Ok(())
+  }
+
+  #[test]
+  fn test_quarantine() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let path = dir.path().join("broken.bin");
+    std::fs::write(&path, [0; 200])?;
+
+    let opts = RepairOptions {
+      quarantine: Some(dir.path().join("quarantine")),
+    };
+    let status = repair_file(&path, &opts)?;
+    assert_eq!(
+      status,
+      RepairStatus::Quarantined(dir.path().join("quarantine").join("broken.bin"))
+    );
+    assert!(!path.exists());
+    Ok(())
+  }
+}
@@ -1,8 +1,26 @@
-use std::{fs, path::PathBuf};
+use std::{
+  fs,
+  io::ErrorKind,
+  path::{Path, PathBuf},
+  time::Duration,
+};
 
-use crate::config::TrackConfig;
+use log::{info, warn};
 
-use super::{error::TrackFileError, trackfile::TrackFile};
+use crate::{
+  config::{Durability, TrackConfig},
+  flight::summary::FlightSummary,
+};
+
+use super::{
+  archive::{unarchive_file, ArchiveStatus},
+  error::TrackFileError,
+  format::Format,
+  mapped::MappedTrackFile,
+  migrate::{migrate_file, replace_file, MigrationResult},
+  repair::{repair_file, RepairOptions},
+  trackfile::TrackFile,
+};
 
 const SUBKEY_LENGTH: usize = 3;
 const NESTING_LEVEL: usize = 2;
@@ -10,12 +28,20 @@ const NESTING_LEVEL: usize = 2;
 #[derive(Debug)]
 pub struct TrackStore {
   folder: String,
+  durability: Durability,
+  fsync_interval: Duration,
+  chained: bool,
+  archive_after: Option<Duration>,
 }
 
 impl TrackStore {
   pub fn new(cfg: &TrackConfig) -> Self {
     Self {
       folder: cfg.folder.to_owned(),
+      durability: cfg.durability,
+      fsync_interval: Duration::from_secs(cfg.fsync_interval),
+      chained: cfg.chained,
+      archive_after: cfg.archive_after.map(Duration::from_secs),
     }
   }
 
@@ -41,7 +67,47 @@ impl TrackStore {
     let path = self.target_dir(flight_id);
     fs::create_dir_all(&path)?;
     let path = path.join(format!("{flight_id}.bin"));
-    TrackFile::new(path, flight_id)
+    let res = TrackFile::new(&path, flight_id, self.chained);
+    let res = match res {
+      Err(
+        err @ (TrackFileError::InvalidFileLength(_, _)
+        | TrackFileError::ChecksumMismatch
+        | TrackFileError::InvalidData(_)),
+      ) => {
+        // most likely the server crashed in the middle of writing an entry,
+        // repair the file so that the flight could be continued
+        warn!("track file {flight_id} is damaged: {err}, repairing");
+        let status = repair_file(&path, &RepairOptions::default())?;
+        warn!("{flight_id}: {status}");
+        TrackFile::open(&path)
+      }
+      res => res,
+    };
+    let mut tf = res?;
+    if tf.is_archived() {
+      // the flight is continued, switch back to the append-friendly layout
+      drop(tf);
+      let status = unarchive_file(&path)?;
+      if status == ArchiveStatus::Unarchived {
+        info!("track file {flight_id} unarchived");
+      }
+      tf = TrackFile::open(&path)?;
+    }
+    if tf.format() < Format::LATEST {
+      // older formats can't hold everything the flight produces now
+      drop(tf);
+      let status = migrate_file(&path, Format::LATEST)?;
+      info!("track file {flight_id} {status}");
+      tf = TrackFile::open(&path)?;
+    }
+    // the summary of a resumed flight is outdated, it's computed again
+    // once the flight is closed, which a crash may prevent
+    match fs::remove_file(self.summary_path(flight_id)) {
+      Ok(()) => {}
+      Err(err) if err.kind() == ErrorKind::NotFound => {}
+      Err(err) => return Err(err.into()),
+    }
+    Ok(tf.with_durability(self.durability, self.fsync_interval))
   }
 
   pub fn open(&self, flight_id: &str) -> Result<TrackFile, TrackFileError> {
@@ -50,4 +116,181 @@ impl TrackStore {
     let path = path.join(format!("{flight_id}.bin"));
     TrackFile::open(path)
   }
+
+  /// Opens a read-only memory mapped view of a track file
+  pub fn open_mapped(&self, flight_id: &str) -> Result<MappedTrackFile, TrackFileError> {
+    self.check_flight_id(flight_id)?;
+    let path = self.target_dir(flight_id);
+    let path = path.join(format!("{flight_id}.bin"));
+    MappedTrackFile::open(path)
+  }
+
+  fn summary_path(&self, flight_id: &str) -> PathBuf {
+    self
+      .target_dir(flight_id)
+      .join(format!("{flight_id}.summary.json"))
+  }
+
+  /// Stores the flight summary next to the track file
+  pub fn save_summary(
+    &self,
+    flight_id: &str,
+    summary: &FlightSummary,
+  ) -> Result<(), TrackFileError> {
+    self.check_flight_id(flight_id)?;
+    replace_file(&self.summary_path(flight_id), |w| {
+      serde_json::to_writer(w, summary).map_err(|err| TrackFileError::InvalidData(err.to_string()))
+    })
+  }
+
+  /// Loads the flight summary, None if it hasn't been computed yet
+  pub fn load_summary(&self, flight_id: &str) -> Result<Option<FlightSummary>, TrackFileError> {
+    self.check_flight_id(flight_id)?;
+    let raw = match fs::read(self.summary_path(flight_id)) {
+      Ok(raw) => raw,
+      Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+      Err(err) => return Err(err.into()),
+    };
+    let summary =
+      serde_json::from_slice(&raw).map_err(|err| TrackFileError::InvalidData(err.to_string()))?;
+    Ok(Some(summary))
+  }
+
+  /// Delay after which finished flights are archived, if archiving is on
+  pub fn archive_after(&self) -> Option<Duration> {
+    self.archive_after
+  }
+
+  /// Lists all the track files in the store
+  pub fn track_files(&self) -> Result<Vec<PathBuf>, TrackFileError> {
+    let mut files = vec![];
+    let folder = Path::new(&self.folder);
+    // nothing has been stored yet
+    if !folder.is_dir() {
+      return Ok(files);
+    }
+    collect_track_files(folder, &mut files)?;
+    files.sort();
+    Ok(files)
+  }
+
+  /// Rewrites every track file in the store using the latest format.
+  /// Must not be run while the service is writing to the store.
+  pub fn migrate(&self) -> Result<Vec<MigrationResult>, TrackFileError> {
+    let results = self
+      .track_files()?
+      .into_iter()
+      .map(|path| {
+        let status = migrate_file(&path, Format::LATEST);
+        MigrationResult { path, status }
+      })
+      .collect();
+    Ok(results)
+  }
+}
+
+fn collect_track_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), TrackFileError> {
+  for entry in fs::read_dir(dir)? {
+    let path = entry?.path();
+    if path.is_dir() {
+      collect_track_files(&path, files)?;
+    } else if path.extension().is_some_and(|ext| ext == "bin") {
+      files.push(path);
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::track::{
+    entry::{Event, EventKind, TrackFileEntry, TrackPoint},
+    trackfile::tests::write_v1_file,
+  };
+  use std::{fs::OpenOptions, os::unix::prelude::FileExt};
+  use tempfile::TempDir;
+
+  fn store(dir: &TempDir) -> TrackStore {
+    TrackStore::new(&TrackConfig {
+      folder: dir.path().join("tracks").to_string_lossy().into_owned(),
+      ..Default::default()
+    })
+  }
+
+  #[test]
+  fn test_repair_on_open() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = TempDir::new()?;
+    let store = store(&dir);
+    let flight_id = "E2B8A9FF-123B-49AB-B330-44CEAB68D465";
+    let mut tf = store.open_or_create(flight_id)?;
+    for ts in 0..3 {
+      let tp = TrackPoint {
+        ts,
+        ..Default::default()
+      };
+      tf.append(&TrackFileEntry::TrackPoint(tp))?;
+    }
+
+    // simulate a crash leaving the header and the file length of the last
+    // entry on disk but not its data
+    let offset = tf.format().header_size() + 2 * tf.format().entry_size();
+    let file = OpenOptions::new().write(true).open(tf.path())?;
+    file.write_all_at(&vec![0; tf.format().entry_size()], offset as u64)?;
+    drop(tf);
+
+    let tf = store.open_or_create(flight_id)?;
+    assert_eq!(tf.count()?, 2);
+    Ok(())
+  }
+
+  #[test]
+  fn test_migrate_on_open() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = TempDir::new()?;
+    let store = store(&dir);
+    let flight_id = "E2B8A9FF-123B-49AB-B330-44CEAB68D465";
+    let path = store.target_dir(flight_id);
+    fs::create_dir_all(&path)?;
+    let path = path.join(format!("{flight_id}.bin"));
+    let tp = TrackFileEntry::TrackPoint(TrackPoint {
+      ts: 1000,
+      ..Default::default()
+    });
+    write_v1_file(&path, std::slice::from_ref(&tp))?;
+
+    // a resumed flight keeps everything it produces
+    let mut tf = store.open_or_create(flight_id)?;
+    assert_eq!(tf.format(), Format::LATEST);
+    tf.set_aircraft("DLH4AB", "A20N", "4AB", "Airbus A320neo")?;
+    tf.append(&TrackFileEntry::Event(Event::new(2000, EventKind::Takeoff)))?;
+
+    let tf = store.open(flight_id)?;
+    assert_eq!(tf.get_departure()?, "EGLL");
+    assert_eq!(tf.get_header()?.get_atc_id(), "DLH4AB");
+    assert_eq!(tf.read_all()?.len(), 2);
+    assert_eq!(tf.read_at(0)?, tp);
+    Ok(())
+  }
+
+  #[test]
+  fn test_migrate_empty_store() -> Result<(), TrackFileError> {
+    let dir = TempDir::new()?;
+    let store = store(&dir);
+    assert!(store.migrate()?.is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn test_resume_drops_summary() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = TempDir::new()?;
+    let store = store(&dir);
+    let flight_id = "E2B8A9FF-123B-49AB-B330-44CEAB68D465";
+    drop(store.open_or_create(flight_id)?);
+    store.save_summary(flight_id, &FlightSummary::default())?;
+    assert!(store.load_summary(flight_id)?.is_some());
+
+    drop(store.open_or_create(flight_id)?);
+    assert!(store.load_summary(flight_id)?.is_none());
+    Ok(())
+  }
 }
@@ -1,78 +1,87 @@
 use super::{
-  entry::{TrackFileEntry, TrackPoint},
+  archive::ArchiveReader,
+  entry::{Event, EventKind, FlightPhase, TrackFileEntry, TrackPoint},
   error::TrackFileError,
+  format::{Format, MAX_HEADER_SIZE},
   header::Header,
+  integrity::{chain_next, chain_seed, unseal, Digest, VerifyReport, VerifyStatus, LINK_SIZE},
+  iter::{find_ts, time_range, Entries, RawEntries, CHUNK_LEN},
 };
+use crate::config::Durability;
 use chrono::{DateTime, Utc};
 use haversine::Units;
 use std::{
-  fmt::Display,
   fs::{File, OpenOptions},
-  io::{Seek, SeekFrom, Write},
-  mem::size_of,
+  io::Write,
+  ops::Range,
   os::unix::prelude::FileExt,
   path::{Path, PathBuf},
-  ptr::slice_from_raw_parts,
+  time::{Duration, Instant},
 };
 
 const NM_IN_KM: f64 = 0.539957;
 
-#[allow(clippy::size_of_in_element_count)]
-fn to_raw<T: Sized>(obj: &T) -> Vec<u8> {
-  let slice = slice_from_raw_parts(obj, size_of::<T>()) as *const [u8];
-  let slice = unsafe { &*slice };
-  slice.into()
-}
-
-fn from_raw<T: Sized + Clone, I: AsRef<str> + Display>(
-  data: &[u8],
-  ident: I,
-) -> std::result::Result<T, TrackFileError> {
-  if data.len() < size_of::<T>() {
-    Err(TrackFileError::InsufficientDataLength(
-      ident.to_string(),
-      data.len(),
-    ))
-  } else {
-    let slice = data as *const [u8] as *const T;
-    let tp = unsafe { &*slice };
-    Ok(tp.clone())
-  }
-}
-
 pub struct TrackFile {
   flight_id: String,
   file: File,
   path: PathBuf,
+  format: Format,
   last_point: Option<TrackPoint>,
+  durability: Durability,
+  sync_interval: Duration,
+  last_sync: Instant,
+  archive: Option<ArchiveReader>,
 }
 
 impl TrackFile {
-  pub fn new<P: AsRef<Path>>(path: P, flight_id: &str) -> Result<Self, TrackFileError> {
+  /// Opens an existing track file or creates a new one. `chained` turns on
+  /// the tamper-evident mode for newly created files.
+  pub fn new<P: AsRef<Path>>(
+    path: P,
+    flight_id: &str,
+    chained: bool,
+  ) -> Result<Self, TrackFileError> {
     let res = Self::open(path.as_ref());
     match res {
       Ok(tf) => Ok(tf),
       Err(err) => match err {
-        TrackFileError::NotFound(_) => Self::create(path, flight_id),
+        TrackFileError::NotFound(_) => {
+          let mut header = Header::new(flight_id)?;
+          if chained {
+            header.set_chained();
+          }
+          Self::create_with_header(path, header)
+        }
         _ => Err(err),
       },
     }
   }
 
   pub fn create<P: AsRef<Path>>(path: P, flight_id: &str) -> Result<Self, TrackFileError> {
+    Self::create_with_header(path, Header::new(flight_id)?)
+  }
+
+  pub fn create_with_header<P: AsRef<Path>>(
+    path: P,
+    header: Header,
+  ) -> Result<Self, TrackFileError> {
     let mut file = OpenOptions::new()
       .create(true)
+      .truncate(true)
       .write(true)
       .read(true)
       .open(&path)?;
-    let header = Header::new(flight_id)?;
-    let raw_header = to_raw(&header);
-    file.write_all(&raw_header)?;
+    file.write_all(&header.encode())?;
     Ok(Self {
-      flight_id: flight_id.to_owned(),
+      flight_id: header.get_flight_id(),
       file,
       path: path.as_ref().to_path_buf(),
+      format: Format::LATEST,
       last_point: None,
+      durability: Durability::None,
+      sync_interval: Duration::ZERO,
+      last_sync: Instant::now(),
+      archive: None,
     })
   }
 
@@ -85,12 +94,22 @@ impl TrackFile {
           flight_id: Default::default(),
           file,
           path,
+          format: Format::LATEST,
           last_point: None,
+          durability: Durability::None,
+          sync_interval: Duration::ZERO,
+          last_sync: Instant::now(),
+          archive: None,
         };
 
+        tf.format = tf.read_file_format()?;
+        let header = tf.read_file_header()?;
+        if header.is_archived() {
+          let count = header.count() as usize;
+          tf.archive = Some(ArchiveReader::open(&tf.file, tf.header_size(), count)?);
+        }
         tf.check()?;
 
-        let header = tf.read_file_header()?;
         tf.flight_id = header.get_flight_id();
         tf.last_point = tf.get_last_point()?;
 
@@ -106,16 +125,23 @@ impl TrackFile {
     }
   }
 
+  /// Sets the fsync policy applied on append. `sync_interval` is only used
+  /// by the periodic policy.
+  pub fn with_durability(mut self, durability: Durability, sync_interval: Duration) -> Self {
+    self.durability = durability;
+    self.sync_interval = sync_interval;
+    self
+  }
+
   fn get_last_point(&self) -> Result<Option<TrackPoint>, TrackFileError> {
     let header = self.get_header()?;
-    let mut idx = header.count as i64 - 1;
-    while idx >= 0 {
-      let entry = self.read_at((header.count - 1) as usize)?;
-      match entry {
-        TrackFileEntry::TrackPoint(tp) => return Ok(Some(tp.clone())),
-        TrackFileEntry::TouchDown(_) => {}
-      }
+    let mut idx = header.count as usize;
+    while idx > 0 {
       idx -= 1;
+      let entry = self.decode_entry(&self.read_raw(idx, 1)?)?;
+      if let TrackFileEntry::TrackPoint(tp) = entry {
+        return Ok(Some(tp));
+      }
     }
     Ok(None)
   }
@@ -126,7 +152,10 @@ impl TrackFile {
       Err(TrackFileError::InvalidMagicNumber)
     } else {
       let meta = std::fs::metadata(&self.path)?;
-      let expected_len = (header.count() as usize) * Self::entry_size() + Self::header_size();
+      let expected_len = match &self.archive {
+        Some(archive) => archive.file_len() as usize,
+        None => (header.count() as usize) * self.entry_size() + self.header_size(),
+      };
       let real_len = meta.len() as usize;
       if real_len != expected_len {
         Err(TrackFileError::InvalidFileLength(expected_len, real_len))
@@ -136,41 +165,51 @@ impl TrackFile {
     }
   }
 
-  fn make_entry_buf() -> Vec<u8> {
-    let buf = vec![0; Self::entry_size()];
-    buf
+  fn entry_size(&self) -> usize {
+    self.format.entry_size()
   }
 
-  fn make_header_buf() -> Vec<u8> {
-    let buf = vec![0; Self::header_size()];
-    buf
+  fn header_size(&self) -> usize {
+    self.format.header_size()
   }
 
-  const fn entry_size() -> usize {
-    size_of::<TrackFileEntry>()
+  fn decode_entry(&self, data: &[u8]) -> Result<TrackFileEntry, TrackFileError> {
+    self.format.decode_entry(data)
   }
 
-  const fn header_size() -> usize {
-    size_of::<Header>()
+  fn read_file_format(&self) -> Result<Format, TrackFileError> {
+    let mut buf = [0; 16];
+    let len = self.file.read_at(&mut buf, 0)?;
+    Format::detect(&buf[..len])
   }
 
   fn read_file_header(&self) -> Result<Header, TrackFileError> {
-    let mut buf = Self::make_header_buf();
-    self.file.read_at(&mut buf, 0)?;
-    from_raw(&buf, "header")
+    let mut buf = vec![0; MAX_HEADER_SIZE];
+    let len = self.file.read_at(&mut buf, 0)?;
+    self.format.decode_header(&buf[..len])
   }
 
   fn write_file_header(&mut self, header: &Header) -> Result<(), TrackFileError> {
-    let buf = to_raw(header);
+    let buf = self.format.encode_header(header);
     self.file.write_at(&buf, 0)?;
     Ok(())
   }
 
-  fn inc(&mut self) -> Result<(), TrackFileError> {
-    let mut header = self.read_file_header()?;
-    header.inc();
-    self.write_file_header(&header)?;
-    Ok(())
+  pub fn format(&self) -> Format {
+    self.format
+  }
+
+  pub fn is_archived(&self) -> bool {
+    self.archive.is_some()
+  }
+
+  /// The last track point of the file, with the distance accumulated
+  pub fn last_point(&self) -> Option<&TrackPoint> {
+    self.last_point.as_ref()
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
   }
 
   pub fn flight_id(&self) -> &str {
@@ -196,17 +235,39 @@ impl TrackFile {
   }
 
   pub fn set_departure(&mut self, departure: &str) -> Result<(), TrackFileError> {
+    self.check_writable()?;
     let mut header = self.read_file_header()?;
     header.set_departure(departure);
     self.write_file_header(&header)
   }
 
   pub fn set_arrival(&mut self, arrival: &str) -> Result<(), TrackFileError> {
+    self.check_writable()?;
     let mut header = self.read_file_header()?;
     header.set_arrival(arrival);
     self.write_file_header(&header)
   }
 
+  pub fn add_landing(&mut self, airport: &str) -> Result<(), TrackFileError> {
+    self.check_writable()?;
+    let mut header = self.read_file_header()?;
+    header.add_landing(airport);
+    self.write_file_header(&header)
+  }
+
+  pub fn set_aircraft(
+    &mut self,
+    atc_id: &str,
+    atc_type: &str,
+    atc_flight_number: &str,
+    title: &str,
+  ) -> Result<(), TrackFileError> {
+    self.check_writable()?;
+    let mut header = self.read_file_header()?;
+    header.set_aircraft(atc_id, atc_type, atc_flight_number, title);
+    self.write_file_header(&header)
+  }
+
   pub fn get_departure(&self) -> Result<String, TrackFileError> {
     let header = self.read_file_header()?;
     Ok(header.get_departure())
@@ -217,31 +278,34 @@ impl TrackFile {
     Ok(header.get_arrival())
   }
 
-  pub fn append(&mut self, e: &TrackFileEntry) -> Result<(), TrackFileError> {
+  /// Phase of the flight as of the last phase event appended
+  pub fn get_phase(&self) -> Result<Option<FlightPhase>, TrackFileError> {
     let header = self.read_file_header()?;
-    let count = header.count() as usize;
-    let offset = if count < 2 {
-      // if less than 2 points exist, append only
-      0
-    } else {
-      let mut last_two = self.read_multiple_at(count - 2, 2)?;
-      let last = last_two.pop().unwrap();
-      let prev = last_two.pop().unwrap();
-      if last == prev && prev == *e {
-        // if the last two points are equal and the new one equals to them
-        // replace the last one, overwriting only timestamp
-        -(Self::entry_size() as i64)
-      } else {
-        // otherwise, append
-        0
-      }
-    };
+    Ok(header.get_phase())
+  }
 
-    if offset == 0 {
-      self.inc()?
+  fn check_writable(&self) -> Result<(), TrackFileError> {
+    if self.is_archived() {
+      Err(TrackFileError::Archived)
+    } else if self.format < Format::LATEST {
+      // older formats have no room for the newer data,
+      // the file has to be migrated first
+      Err(TrackFileError::UnsupportedVersion(self.format.version()))
+    } else {
+      Ok(())
     }
+  }
 
-    let data = match e {
+  /// Appends the entry, returns its position and the entry as written,
+  /// e.g. a track point with the distance accumulated. A point repeating
+  /// the last two ones replaces the last one and keeps its position,
+  /// except in chained files which keep every entry.
+  pub fn append(&mut self, e: &TrackFileEntry) -> Result<(usize, TrackFileEntry), TrackFileError> {
+    self.check_writable()?;
+    let mut header = self.read_file_header()?;
+    let count = header.count() as usize;
+
+    let entry = match e {
       TrackFileEntry::TrackPoint(tp) => {
         // replace trackpoint with a trackpoint with distance calculated;
         let last_point = self.last_point.as_ref().unwrap_or(tp).clone();
@@ -253,14 +317,67 @@ impl TrackFile {
 
         self.last_point = Some(new_point.clone());
 
-        let e = TrackFileEntry::TrackPoint(new_point);
-        to_raw(&e)
+        TrackFileEntry::TrackPoint(new_point)
       }
-      TrackFileEntry::TouchDown(_) => to_raw(e),
+      _ => e.clone(),
     };
 
-    self.file.seek(SeekFrom::End(offset))?;
-    self.file.write_all(&data)?;
+    // decided before anything is written, so that a parked aircraft
+    // doesn't grow the track. Chained files aren't deduplicated as the
+    // link of a replaced entry would need the digest before it.
+    let replace_last = !header.is_chained() && count >= 2 && {
+      let last_two = self.read_multiple_at(count - 2, 2)?;
+      last_two.iter().all(|last| *last == entry)
+    };
+    let pos = if replace_last { count - 1 } else { count };
+
+    let link = header
+      .is_chained()
+      .then(|| chain_next(&header.digest, &self.format.encode_payload(&entry)));
+    let data = self.format.encode_linked_entry(&entry, link.as_ref());
+
+    // the entry is written before the header so that the header never
+    // claims entries which are not in the file yet. Both are synced
+    // together. A crash in between leaves extra bytes, which repair cuts
+    // off, or a complete trailing entry, which repair keeps and covers
+    // by the header. A torn replacement of the last entry fails its
+    // checksum and is dropped by repair, losing only a repeated point.
+    let offset = self.header_size() + pos * self.entry_size();
+    self.file.write_all_at(&data, offset as u64)?;
+
+    if let Some(link) = link {
+      header.digest = link;
+    }
+    if let TrackFileEntry::Event(Event {
+      kind: EventKind::Phase(phase),
+      ..
+    }) = &entry
+    {
+      header.phase = Some(*phase);
+    }
+    if replace_last {
+      header.touch();
+    } else {
+      header.inc();
+    }
+    self.write_file_header(&header)?;
+
+    match self.durability {
+      Durability::None => {}
+      Durability::Periodic => {
+        if self.last_sync.elapsed() >= self.sync_interval {
+          self.sync()?;
+        }
+      }
+      Durability::Ack => self.sync()?,
+    }
+    Ok((pos, entry))
+  }
+
+  /// Flushes all the written data to disk
+  pub fn sync(&mut self) -> Result<(), TrackFileError> {
+    self.file.sync_data()?;
+    self.last_sync = Instant::now();
     Ok(())
   }
 
@@ -269,11 +386,8 @@ impl TrackFile {
     if pos as u64 >= header.count() {
       Err(TrackFileError::IndexError(pos))
     } else {
-      let mut buf = Self::make_entry_buf();
-      let offset = Self::header_size() + pos * Self::entry_size();
-      self.file.read_at(&mut buf, offset as u64)?;
-      let e = from_raw(&buf, "track entry")?;
-      Ok(e)
+      let buf = self.read_raw(pos, 1)?;
+      self.decode_entry(&buf)
     }
   }
 
@@ -294,48 +408,149 @@ impl TrackFile {
       return Ok(Vec::new());
     }
 
-    let mut buf = vec![];
-    let entry_len = Self::entry_size();
-    buf.resize(len * entry_len, 0);
-
-    let offset = Self::header_size() + pos * entry_len;
-    self.file.read_at(&mut buf, offset as u64)?;
-
+    let buf = self.read_raw(pos, len)?;
     let mut entries = vec![];
-    for idx in 0..len {
-      let start = idx * entry_len;
-      let end = (idx + 1) * entry_len;
-      let e = from_raw(&buf[start..end], "track entry")?;
-      entries.push(e);
+    for chunk in buf.chunks_exact(self.entry_size()) {
+      entries.push(self.decode_entry(chunk)?);
     }
 
     Ok(entries)
   }
 
   pub fn read_all(&self) -> Result<Vec<TrackFileEntry>, TrackFileError> {
-    let header = self.read_file_header()?;
+    self.entries()?.collect()
+  }
 
-    let mut buf = Self::make_entry_buf();
-    let mut res = vec![];
-    for idx in 0..header.count() {
-      let idx = idx as usize;
-      let offset = Self::header_size() + idx * Self::entry_size();
-      self.file.read_at(&mut buf, offset as u64)?;
-      let tp = from_raw(&buf, "track entry")?;
-      res.push(tp);
-    }
-    Ok(res)
+  /// Lazily iterates over all the entries present in the file
+  pub fn entries(&self) -> Result<Entries<'_, Self>, TrackFileError> {
+    self.entries_range(0..usize::MAX)
+  }
+
+  /// Lazily iterates over the entries within the range of indices,
+  /// the range is clamped to the number of entries
+  pub fn entries_range(&self, range: Range<usize>) -> Result<Entries<'_, Self>, TrackFileError> {
+    let count = self.read_file_header()?.count() as usize;
+    Ok(Entries::new(self, range, count))
+  }
+
+  /// Lazily iterates over the entries with timestamps within
+  /// `start_at..end_at`, unbounded on the right if `end_at` is not set
+  pub fn entries_time_range(
+    &self,
+    start_at: u64,
+    end_at: Option<u64>,
+  ) -> Result<Entries<'_, Self>, TrackFileError> {
+    let count = self.read_file_header()?.count() as usize;
+    let range = time_range(self, count, start_at, end_at)?;
+    Ok(Entries::new(self, range, count))
+  }
+
+  /// Returns the index of the first entry with a timestamp at or after `ts`,
+  /// or the number of entries if there's no such entry
+  pub fn find_ts(&self, ts: u64) -> Result<usize, TrackFileError> {
+    let count = self.read_file_header()?.count() as usize;
+    find_ts(self, count, ts)
+  }
+
+  /// Reads the entries with timestamps within `start_at..end_at`,
+  /// the range is unbounded on the right if `end_at` is not set
+  pub fn read_time_range(
+    &self,
+    start_at: u64,
+    end_at: Option<u64>,
+  ) -> Result<Vec<TrackFileEntry>, TrackFileError> {
+    self.entries_time_range(start_at, end_at)?.collect()
   }
 
   pub fn get_header(&self) -> Result<Header, TrackFileError> {
     self.read_file_header()
   }
+
+  /// Checks every entry against its checksum and, for chained files,
+  /// against the hash chain, reporting the first bad entry. The entries
+  /// are read in chunks of `CHUNK_LEN`.
+  pub fn verify(&self) -> Result<VerifyReport, TrackFileError> {
+    let header = self.read_file_header()?;
+    let count = header.count();
+    let mut chain = header.is_chained().then(|| chain_seed(&self.flight_id));
+
+    let mut pos = 0;
+    while pos < count as usize {
+      let len = CHUNK_LEN.min(count as usize - pos);
+      let buf = self.read_raw(pos, len)?;
+      for (idx, data) in buf.chunks_exact(self.entry_size()).enumerate() {
+        if let Err(err) = self.verify_entry(data, chain.as_mut()) {
+          return Ok(VerifyReport {
+            count,
+            status: VerifyStatus::BadEntry {
+              index: pos + idx,
+              reason: err.to_string(),
+            },
+            digest: header.digest(),
+          });
+        }
+      }
+      pos += len;
+    }
+
+    let status = match chain {
+      Some(chain) if chain != header.digest => VerifyStatus::DigestMismatch,
+      _ => VerifyStatus::Ok,
+    };
+    Ok(VerifyReport {
+      count,
+      status,
+      digest: header.digest(),
+    })
+  }
+
+  fn verify_entry(&self, data: &[u8], chain: Option<&mut Digest>) -> Result<(), TrackFileError> {
+    if !self.format.is_sealed() {
+      self.decode_entry(data)?;
+      return Ok(());
+    }
+
+    let (payload, link) = unseal(data)?;
+    TrackFileEntry::decode(payload)?;
+    if let Some(chain) = chain {
+      let next = chain_next(chain, payload);
+      if next[..LINK_SIZE] != *link {
+        return Err(TrackFileError::InvalidData("chain link mismatch".into()));
+      }
+      *chain = next;
+    }
+    Ok(())
+  }
+}
+
+impl RawEntries for TrackFile {
+  fn format(&self) -> Format {
+    self.format
+  }
+
+  fn read_raw(&self, pos: usize, len: usize) -> Result<Vec<u8>, TrackFileError> {
+    let entry_size = self.entry_size();
+    match &self.archive {
+      Some(archive) => archive.read_raw(&self.file, pos, len, entry_size),
+      None => {
+        let mut buf = vec![0; len * entry_size];
+        let offset = self.header_size() + pos * entry_size;
+        self.file.read_exact_at(&mut buf, offset as u64)?;
+        Ok(buf)
+      }
+    }
+  }
 }
 
 #[cfg(test)]
 pub mod tests {
 
   use super::*;
+  use crate::track::{
+    entry::TouchDown,
+    legacy,
+    repair::{repair_file, RepairOptions, RepairStatus},
+  };
   use tempfile::NamedTempFile;
 
   #[test]
@@ -343,15 +558,19 @@ pub mod tests {
     let temp = NamedTempFile::new()?;
     let mut tf = TrackFile::create(temp.path(), "E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
 
-    let mut tp = TrackPoint::default();
-    tp.lng = -0.4947472;
-    tp.lat = 51.4668786;
+    let tp = TrackPoint {
+      lng: -0.4947472,
+      lat: 51.4668786,
+      ..Default::default()
+    };
     let e = TrackFileEntry::TrackPoint(tp);
     tf.append(&e)?;
 
-    let mut tp = TrackPoint::default();
-    tp.lng = -0.1846378;
-    tp.lat = 51.1536621;
+    let tp = TrackPoint {
+      lng: -0.1846378,
+      lat: 51.1536621,
+      ..Default::default()
+    };
     let e = TrackFileEntry::TrackPoint(tp);
     tf.append(&e)?;
 
@@ -362,12 +581,14 @@ pub mod tests {
         let apr_distance = (tp.distance * 1000.0).round() as u64;
         assert_eq!(apr_distance, 22116) // 22.1159 nm between Heathrow and Gatwick
       }
-      TrackFileEntry::TouchDown(_) => assert!(false),
+      _ => panic!("expected a track point"),
     }
 
-    let mut tp = TrackPoint::default();
-    tp.lng = -0.4947472;
-    tp.lat = 51.4668786;
+    let tp = TrackPoint {
+      lng: -0.4947472,
+      lat: 51.4668786,
+      ..Default::default()
+    };
     let e = TrackFileEntry::TrackPoint(tp);
     tf.append(&e)?;
 
@@ -378,9 +599,304 @@ pub mod tests {
         let apr_distance = (tp.distance * 1000.0).round() as u64;
         assert_eq!(apr_distance, 44232) // 2 * 22.1159 nm Heathrow to Gatwick and back
       }
-      TrackFileEntry::TouchDown(_) => assert!(false),
+      _ => panic!("expected a track point"),
+    }
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_last_point() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let mut tf = TrackFile::create(temp.path(), "E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    let tp = TrackPoint {
+      lng: -0.4947472,
+      lat: 51.4668786,
+      ..Default::default()
+    };
+    tf.append(&TrackFileEntry::TrackPoint(tp))?;
+    tf.append(&TrackFileEntry::TouchDown(TouchDown {
+      ts: 1,
+      bank: 0.0,
+      hdg_mag: 0.0,
+      hdg_true: 0.0,
+      vel_nrm: 0.0,
+      pitch: 0.0,
+      lat: 51.4668786,
+      lng: -0.4947472,
+      ..Default::default()
+    }))?;
+    drop(tf);
+
+    // the last point is looked up past the trailing touchdown on reopen
+    let mut tf = TrackFile::open(temp.path())?;
+    let tp = TrackPoint {
+      lng: -0.1846378,
+      lat: 51.1536621,
+      ..Default::default()
+    };
+    tf.append(&TrackFileEntry::TrackPoint(tp))?;
+    match tf.read_at(2)? {
+      TrackFileEntry::TrackPoint(tp) => assert_eq!((tp.distance * 1000.0).round() as u64, 22116),
+      _ => panic!("expected a track point"),
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_append() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let mut tf = TrackFile::create(temp.path(), "E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    let tp = TrackPoint {
+      lat: 51.4668786,
+      lng: -0.4947472,
+      ..Default::default()
+    };
+    // a point repeating the last two replaces the last one
+    let mut positions = vec![];
+    for ts in 0..4 {
+      let (pos, _) = tf.append(&TrackFileEntry::TrackPoint(TrackPoint { ts, ..tp.clone() }))?;
+      positions.push(pos);
+    }
+    assert_eq!(positions, vec![0, 1, 1, 1]);
+    let td = TrackFileEntry::TouchDown(TouchDown {
+      ts: 4,
+      ..Default::default()
+    });
+    assert_eq!(tf.append(&td)?.0, 2);
+    let (pos, _) = tf.append(&TrackFileEntry::TrackPoint(TrackPoint {
+      ts: 5,
+      ..tp.clone()
+    }))?;
+    assert_eq!(pos, 3);
+
+    let ts: Vec<u64> = tf.read_all()?.iter().map(|e| e.ts()).collect();
+    assert_eq!(ts, vec![0, 3, 4, 5]);
+
+    // the header follows the phase events
+    assert_eq!(tf.get_phase()?, None);
+    let event = Event::new(6, EventKind::Phase(FlightPhase::TaxiIn));
+    tf.append(&TrackFileEntry::Event(event))?;
+    assert_eq!(tf.get_phase()?, Some(FlightPhase::TaxiIn));
+    let len = std::fs::metadata(temp.path())?.len() as usize;
+    assert_eq!(
+      len,
+      tf.format().header_size() + 5 * tf.format().entry_size()
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_durability() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let tf = TrackFile::create(temp.path(), "E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    let entry = |ts| TrackFileEntry::TrackPoint(TrackPoint::at(ts).pos(0.0, ts as f64));
+    let created = tf.last_sync;
+
+    let mut tf = tf.with_durability(Durability::None, Duration::ZERO);
+    tf.append(&entry(0))?;
+    assert_eq!(tf.last_sync, created);
+
+    let mut tf = tf.with_durability(Durability::Periodic, Duration::from_secs(3600));
+    tf.append(&entry(1))?;
+    assert_eq!(tf.last_sync, created);
+
+    let mut tf = tf.with_durability(Durability::Periodic, Duration::ZERO);
+    tf.append(&entry(2))?;
+    assert!(tf.last_sync > created);
+
+    let synced = tf.last_sync;
+    let mut tf = tf.with_durability(Durability::Ack, Duration::from_secs(3600));
+    tf.append(&entry(3))?;
+    assert!(tf.last_sync > synced);
+    assert_eq!(tf.count()?, 4);
+    Ok(())
+  }
+
+  #[test]
+  fn test_entry_without_header() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let mut tf = TrackFile::create(temp.path(), "E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    let tp = TrackPoint {
+      ts: 1000,
+      ..Default::default()
+    };
+    tf.append(&TrackFileEntry::TrackPoint(tp))?;
+
+    // simulate a crash after the entry has been written
+    // but before the header count has been incremented
+    let mut header = tf.get_header()?;
+    header.count -= 1;
+    tf.write_file_header(&header)?;
+    drop(tf);
+    assert!(matches!(
+      TrackFile::open(temp.path()),
+      Err(TrackFileError::InvalidFileLength(_, _))
+    ));
+
+    // the complete entry is kept by repair
+    let status = repair_file(temp.path(), &RepairOptions::default())?;
+    assert!(matches!(status, RepairStatus::Repaired { count: 1, .. }));
+    assert_eq!(TrackFile::open(temp.path())?.read_at(0)?.ts(), 1000);
+    Ok(())
+  }
+
+  pub fn write_v1_file(
+    path: &Path,
+    entries: &[TrackFileEntry],
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    let mut header = Header::new("E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    header.version = legacy::HEADER_VERSION;
+    header.count = entries.len() as u64;
+    header.set_departure("EGLL");
+    let mut data = legacy::encode_header(&header);
+    for e in entries {
+      data.extend(legacy::encode_entry(e));
     }
+    std::fs::write(path, data)?;
+    Ok(())
+  }
+
+  #[test]
+  fn test_read_v1() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let mut tp = TrackPoint {
+      ts: 1000,
+      lat: 51.4668786,
+      lng: -0.4947472,
+      on_gnd: true,
+      gear_pct: 100,
+      ..Default::default()
+    };
+    write_v1_file(temp.path(), &[TrackFileEntry::TrackPoint(tp.clone())])?;
+
+    let mut tf = TrackFile::open(temp.path())?;
+    assert_eq!(tf.get_header()?.version(), legacy::HEADER_VERSION);
+    assert_eq!(tf.flight_id(), "E2B8A9FF-123B-49AB-B330-44CEAB68D465");
+    assert_eq!(tf.get_departure()?, "EGLL");
+    assert_eq!(tf.read_at(0)?, TrackFileEntry::TrackPoint(tp.clone()));
+
+    // v1 files have to be migrated before they are appended to
+    tp.ts = 2000;
+    let res = tf.append(&TrackFileEntry::TrackPoint(tp));
+    assert!(matches!(res, Err(TrackFileError::UnsupportedVersion(1))));
+    assert!(tf.set_arrival("EGKK").is_err());
+    let tf = TrackFile::open(temp.path())?;
+    assert_eq!(tf.count()?, 1);
+    Ok(())
+  }
+
+  #[test]
+  fn test_invalid_entry() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let mut tf = TrackFile::create(temp.path(), "E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    tf.append(&TrackFileEntry::TrackPoint(TrackPoint::default()))?;
+
+    // corrupt the ap_master flag of the only entry
+    let mut payload = TrackFileEntry::TrackPoint(TrackPoint::default()).encode();
+    payload[1 + 11 * 8] = 7;
+    let res = TrackFileEntry::decode(&payload);
+    assert!(matches!(res, Err(TrackFileError::InvalidData(_))));
+
+    let offset = tf.format().header_size() + 1 + 11 * 8;
+    tf.file.write_at(&[7], offset as u64)?;
+    let res = tf.read_at(0);
+    assert!(matches!(res, Err(TrackFileError::ChecksumMismatch)));
+
+    let report = tf.verify()?;
+    assert!(matches!(
+      report.status,
+      VerifyStatus::BadEntry { index: 0, .. }
+    ));
+    Ok(())
+  }
 
+  #[test]
+  fn test_find_ts() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let mut tf = TrackFile::create(temp.path(), "E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    for ts in 1..=10 {
+      let tp = TrackPoint {
+        ts: ts * 1000,
+        lat: ts as f64,
+        ..Default::default()
+      };
+      tf.append(&TrackFileEntry::TrackPoint(tp))?;
+    }
+
+    assert_eq!(tf.find_ts(0)?, 0);
+    assert_eq!(tf.find_ts(1000)?, 0);
+    assert_eq!(tf.find_ts(1001)?, 1);
+    assert_eq!(tf.find_ts(5000)?, 4);
+    assert_eq!(tf.find_ts(10001)?, 10);
+
+    let entries = tf.read_time_range(3000, Some(6000))?;
+    let ts: Vec<u64> = entries.iter().map(|e| e.ts()).collect();
+    assert_eq!(ts, vec![3000, 4000, 5000]);
+    assert_eq!(tf.read_time_range(9500, None)?.len(), 1);
+    assert!(tf.read_time_range(6000, Some(3000))?.is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn test_chained() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let mut header = Header::new("E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    header.set_chained();
+    let mut tf = TrackFile::create_with_header(temp.path(), header)?;
+    for ts in 0..5 {
+      let tp = TrackPoint {
+        ts,
+        lat: 51.0 + ts as f64 / 100.0,
+        ..Default::default()
+      };
+      tf.append(&TrackFileEntry::TrackPoint(tp))?;
+    }
+
+    let report = tf.verify()?;
+    assert_eq!(report.status, VerifyStatus::Ok);
+    assert!(report.digest.is_some());
+
+    // rewrite the entry with a valid checksum, as someone editing
+    // the file on purpose would do
+    let tp = TrackPoint {
+      ts: 2,
+      lat: 48.0,
+      ..Default::default()
+    };
+    let data = tf.format().encode_entry(&TrackFileEntry::TrackPoint(tp));
+    let offset = tf.format().header_size() + 2 * tf.format().entry_size();
+    tf.file.write_at(&data, offset as u64)?;
+    assert!(tf.read_at(2).is_ok());
+
+    let report = tf.verify()?;
+    assert!(matches!(
+      report.status,
+      VerifyStatus::BadEntry { index: 2, .. }
+    ));
+    Ok(())
+  }
+
+  #[test]
+  fn test_verify_chunks() -> Result<(), Box<dyn std::error::Error>> {
+    let temp = NamedTempFile::new()?;
+    let mut header = Header::new("E2B8A9FF-123B-49AB-B330-44CEAB68D465")?;
+    header.set_chained();
+    let mut tf = TrackFile::create_with_header(temp.path(), header)?;
+    for ts in 0..2500 {
+      let tp = TrackPoint::at(ts).pos(51.0 + ts as f64 / 10000.0, 0.0);
+      tf.append(&TrackFileEntry::TrackPoint(tp))?;
+    }
+    assert_eq!(tf.verify()?.status, VerifyStatus::Ok);
+
+    // the bad entry is reported by its position in the file,
+    // not in the chunk
+    let offset = tf.format().header_size() + 2100 * tf.format().entry_size() + 1;
+    tf.file.write_at(&[0xff], offset as u64)?;
+    assert!(matches!(
+      tf.verify()?.status,
+      VerifyStatus::BadEntry { index: 2100, .. }
+    ));
     Ok(())
   }
 }
@@ -1,9 +1,17 @@
 use chrono::{DateTime, Utc};
 use clap::Parser;
 use serde::Serialize;
+use std::path::PathBuf;
+use tm_grpc::flight::{
+  landing::{self, analyze_landings},
+  summary::FlightSummary,
+};
 use tm_grpc::track::{
   entry::{self, TrackFileEntry},
   header::Header,
+  integrity::{to_hex, VerifyStatus},
+  mapped::MappedTrackFile,
+  repair::{repair_file, RepairOptions, RepairStatus},
   trackfile::TrackFile,
 };
 
@@ -17,6 +25,59 @@ pub struct Args {
 
   #[arg(long)]
   json: bool,
+
+  /// repair a truncated or partially written file before dumping
+  #[arg(long)]
+  repair: bool,
+
+  /// move unrecoverable files to this folder when repairing
+  #[arg(long)]
+  quarantine: Option<PathBuf>,
+
+  /// verify entry checksums and the hash chain
+  #[arg(long)]
+  verify: bool,
+
+  /// include the landing analysis
+  #[arg(short)]
+  landings: bool,
+
+  /// include the flight summary
+  #[arg(short)]
+  summary: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Summary {
+  pub block_off: Option<DateTime<Utc>>,
+  pub takeoff: Option<DateTime<Utc>>,
+  pub landing: Option<DateTime<Utc>>,
+  pub block_on: Option<DateTime<Utc>>,
+  pub air_time_secs: u64,
+  pub distance: f64,
+  pub max_alt: f64,
+  pub max_gs: f64,
+  pub avg_cruise_alt: Option<f64>,
+  pub landings: u32,
+  pub gaps: u32,
+}
+
+impl From<FlightSummary> for Summary {
+  fn from(value: FlightSummary) -> Self {
+    Self {
+      block_off: value.block_off.map(datetime_from_timestamp),
+      takeoff: value.takeoff.map(datetime_from_timestamp),
+      landing: value.landing.map(datetime_from_timestamp),
+      block_on: value.block_on.map(datetime_from_timestamp),
+      air_time_secs: value.air_time / 1000,
+      distance: value.distance,
+      max_alt: value.max_alt,
+      max_gs: value.max_gs,
+      avg_cruise_alt: value.avg_cruise_alt,
+      landings: value.landings,
+      gaps: value.gaps,
+    }
+  }
 }
 
 #[derive(Debug, Serialize)]
@@ -40,6 +101,26 @@ struct TrackPoint {
   pub wind_vel: f64,
   pub wind_dir: f64,
   pub distance: f64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub vs: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub pitch: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub bank: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub hdg_mag: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub g_force: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub fuel_qty: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub xpdr: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub baro: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub eng_running: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub parking_brake: Option<bool>,
 }
 
 impl From<entry::TrackPoint> for TrackPoint {
@@ -64,6 +145,16 @@ impl From<entry::TrackPoint> for TrackPoint {
       wind_vel: value.wind_vel,
       wind_dir: value.wind_dir,
       distance: value.distance,
+      vs: value.vs,
+      pitch: value.pitch,
+      bank: value.bank,
+      hdg_mag: value.hdg_mag,
+      g_force: value.g_force,
+      fuel_qty: value.fuel_qty,
+      xpdr: value.xpdr,
+      baro: value.baro,
+      eng_running: value.eng_running,
+      parking_brake: value.parking_brake,
     }
   }
 }
@@ -78,6 +169,29 @@ struct TouchDown {
   pub pitch: f64,
   pub lat: f64,
   pub lng: f64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub g_force: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub gs: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub ias: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub wind_vel: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub wind_dir: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub crab_angle: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub on_rwy: Option<bool>,
+  pub inferred: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub runway: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub threshold_distance: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub centreline_offset: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub crosswind: Option<f64>,
 }
 
 impl From<entry::TouchDown> for TouchDown {
@@ -91,6 +205,113 @@ impl From<entry::TouchDown> for TouchDown {
       pitch: value.pitch,
       lat: value.lat,
       lng: value.lng,
+      g_force: value.g_force,
+      gs: value.gs,
+      ias: value.ias,
+      wind_vel: value.wind_vel,
+      wind_dir: value.wind_dir,
+      crab_angle: value.crab_angle,
+      on_rwy: value.on_rwy,
+      inferred: value.inferred,
+      runway: value.runway.map(|rwy| rwy.to_string()),
+      threshold_distance: value.threshold_distance,
+      centreline_offset: value.centreline_offset,
+      crosswind: value.crosswind,
+    }
+  }
+}
+
+#[derive(Debug, Serialize)]
+struct Event {
+  pub ts: DateTime<Utc>,
+  pub event: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub runway: Option<String>,
+}
+
+impl From<entry::Event> for Event {
+  fn from(value: entry::Event) -> Self {
+    Self {
+      ts: datetime_from_timestamp(value.ts),
+      event: value.kind.to_string(),
+      runway: value.runway.map(|rwy| rwy.to_string()),
+    }
+  }
+}
+
+#[derive(Debug, Serialize)]
+struct StabilityGate {
+  pub height: f64,
+  pub ias: f64,
+  pub on_speed: bool,
+  pub gear_down: bool,
+  pub flaps_set: bool,
+  pub stable: bool,
+}
+
+impl From<landing::StabilityGate> for StabilityGate {
+  fn from(value: landing::StabilityGate) -> Self {
+    Self {
+      height: value.height,
+      ias: value.ias,
+      on_speed: value.on_speed,
+      gear_down: value.gear_down,
+      flaps_set: value.flaps_set,
+      stable: value.is_stable(),
+    }
+  }
+}
+
+#[derive(Debug, Serialize)]
+struct LandingReport {
+  pub ts: DateTime<Utc>,
+  pub lat: f64,
+  pub lng: f64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub runway: Option<String>,
+  pub inferred: bool,
+  pub vs: f64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub g_force: Option<f64>,
+  pub bounces: u32,
+  pub bank: f64,
+  pub pitch: f64,
+  pub bank_exceeded: bool,
+  pub pitch_exceeded: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub float_distance: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub threshold_distance: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub centreline_offset: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub crosswind: Option<f64>,
+  pub gates: Vec<StabilityGate>,
+  pub stable: bool,
+}
+
+impl From<landing::LandingReport> for LandingReport {
+  fn from(value: landing::LandingReport) -> Self {
+    let stable = value.is_stable();
+    Self {
+      ts: datetime_from_timestamp(value.ts),
+      lat: value.lat,
+      lng: value.lng,
+      runway: value.runway.map(|rwy| rwy.to_string()),
+      inferred: value.inferred,
+      vs: value.vs,
+      g_force: value.g_force,
+      bounces: value.bounces,
+      bank: value.bank,
+      pitch: value.pitch,
+      bank_exceeded: value.bank_exceeded,
+      pitch_exceeded: value.pitch_exceeded,
+      float_distance: value.float_distance,
+      threshold_distance: value.threshold_distance,
+      centreline_offset: value.centreline_offset,
+      crosswind: value.crosswind,
+      gates: value.gates.into_iter().map(|g| g.into()).collect(),
+      stable,
     }
   }
 }
@@ -104,10 +325,23 @@ struct TrackDump {
   pub flight_id: String,
   pub departure: String,
   pub arrival: String,
+  pub landings: Vec<String>,
+  pub atc_id: String,
+  pub atc_type: String,
+  pub atc_flight_number: String,
+  pub title: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub digest: Option<String>,
   #[serde(skip_serializing_if = "Option::is_none")]
   pub points: Option<Vec<TrackPoint>>,
   #[serde(skip_serializing_if = "Option::is_none")]
   pub touchdowns: Option<Vec<TouchDown>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub events: Option<Vec<Event>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub landing_reports: Option<Vec<LandingReport>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub summary: Option<Summary>,
 }
 
 impl From<Header> for TrackDump {
@@ -120,8 +354,17 @@ impl From<Header> for TrackDump {
       flight_id: value.get_flight_id(),
       departure: value.get_departure(),
       arrival: value.get_arrival(),
+      landings: value.get_landings(),
+      atc_id: value.get_atc_id(),
+      atc_type: value.get_atc_type(),
+      atc_flight_number: value.get_atc_flight_number(),
+      title: value.get_title(),
+      digest: value.digest().as_ref().map(to_hex),
       points: None,
       touchdowns: None,
+      events: None,
+      landing_reports: None,
+      summary: None,
     }
   }
 }
@@ -134,20 +377,60 @@ fn datetime_from_timestamp(ts: u64) -> DateTime<Utc> {
 
 pub fn dump_trackfile() -> Result<(), Box<dyn std::error::Error>> {
   let args = Args::parse();
+
+  if args.repair {
+    let opts = RepairOptions {
+      quarantine: args.quarantine.clone(),
+    };
+    let status = repair_file(&args.filename, &opts)?;
+    eprintln!("{status}");
+    if let RepairStatus::Quarantined(_) = status {
+      return Ok(());
+    }
+  }
+
   let tf = TrackFile::open(&args.filename)?;
   let mut dump: TrackDump = tf.get_header()?.into();
 
+  if args.verify {
+    let report = tf.verify()?;
+    match report.status {
+      VerifyStatus::Ok => eprintln!("all {} entries are intact", report.count),
+      VerifyStatus::BadEntry { index, reason } => {
+        eprintln!("entry {index} of {} is corrupted: {reason}", report.count)
+      }
+      VerifyStatus::DigestMismatch => {
+        eprintln!("entries are intact but don't match the header digest")
+      }
+    }
+  }
+
   if args.points {
     let mut points = vec![];
     let mut touchdowns = vec![];
-    for entry in tf.read_all()? {
-      match entry {
+    let mut events = vec![];
+    let mtf = MappedTrackFile::open(&args.filename)?;
+    for entry in mtf.entries() {
+      match entry? {
         TrackFileEntry::TrackPoint(tp) => points.push(tp.into()),
         TrackFileEntry::TouchDown(td) => touchdowns.push(td.into()),
+        TrackFileEntry::Event(ev) => events.push(ev.into()),
       }
     }
     dump.points = Some(points);
     dump.touchdowns = Some(touchdowns);
+    dump.events = Some(events);
+  }
+
+  if args.landings {
+    let mtf = MappedTrackFile::open(&args.filename)?;
+    let landings = analyze_landings(mtf.entries())?;
+    dump.landing_reports = Some(landings.into_iter().map(|l| l.into()).collect());
+  }
+
+  if args.summary {
+    let mtf = MappedTrackFile::open(&args.filename)?;
+    dump.summary = Some(FlightSummary::compute(mtf.entries())?.into());
   }
 
   let dump = if args.json {
@@ -42,7 +42,9 @@ impl From<SimwatchTrackPoint> for TrackMessage {
         on_rwy: false,
         wind_vel: 0.0,
         wind_dir: 0.0,
+        ..Default::default()
       })),
+      ..Default::default()
     }
   }
 }
@@ -87,11 +89,10 @@ impl SimwatchReader {
 impl TrackReader for SimwatchReader {
   async fn read(&self) -> Result<Receiver<TrackMessage>, Box<dyn Error>> {
     let (tx, rx) = mpsc::channel(1024);
-    let points: Vec<SimwatchTrackPoint> =
-      self.data.as_ref().unwrap().track.iter().cloned().collect();
-    if points.len() > 0 {
+    let points: Vec<SimwatchTrackPoint> = self.data.as_ref().unwrap().track.to_vec();
+    if !points.is_empty() {
       let time_start = points[0].ts as i64;
-      let now = Utc::now().timestamp_millis() as i64;
+      let now = Utc::now().timestamp_millis();
       let timediff = now - time_start;
       println!(
         "Time passed since the track was recorded {:?}",
@@ -101,10 +102,10 @@ impl TrackReader for SimwatchReader {
       tokio::spawn(async move {
         for point in points {
           let ts = point.ts as i64;
-          let adj_now = Utc::now().timestamp_millis() as i64 - timediff;
+          let adj_now = Utc::now().timestamp_millis() - timediff;
 
           if ts > adj_now {
-            let sleep_time = (ts - adj_now) as i64;
+            let sleep_time = ts - adj_now;
             if sleep_time > 0 {
               let sleep_time = if sleep_time > 10000 {
                 10000
@@ -3,10 +3,7 @@ use std::time::Duration;
 use super::TrackReader;
 use async_trait::async_trait;
 use chrono::Utc;
-use tm_grpc::{
-  service::tangomike::TrackMessage,
-  track::{entry::TrackFileEntry, trackfile::TrackFile},
-};
+use tm_grpc::{service::tangomike::TrackMessage, track::trackfile::TrackFile};
 use tokio::{
   sync::mpsc::{self, Receiver},
   time::sleep,
@@ -33,23 +30,22 @@ impl TrackReader for TrackFileReader {
 
     let count = header.count();
     if count > 1 {
-      let first = tf.read_at(0)?;
-      let ts = match first {
-        TrackFileEntry::TrackPoint(tp) => tp.ts,
-        TrackFileEntry::TouchDown(td) => td.ts,
-      };
+      let ts = tf.read_at(0)?.ts();
       let now = Utc::now().timestamp_millis() as u64;
       let timediff = now - ts;
 
       tokio::spawn(async move {
-        for i in 0..count {
-          let res = tf.read_at(i as usize);
+        let entries = match tf.entries() {
+          Ok(entries) => entries,
+          Err(err) => {
+            println!("Error reading track file: {err}");
+            return;
+          }
+        };
+        for res in entries {
           if let Ok(entry) = res {
             let adj_now = Utc::now().timestamp_millis() as u64 - timediff;
-            let ts = match &entry {
-              TrackFileEntry::TrackPoint(tp) => tp.ts,
-              TrackFileEntry::TouchDown(td) => td.ts,
-            };
+            let ts = entry.ts();
 
             if ts > adj_now {
               let sleep_time = Duration::from_millis(ts - adj_now);